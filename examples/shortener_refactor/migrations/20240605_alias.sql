@@ -0,0 +1,5 @@
+-- custom aliases: ids become variable-length and the url dedupe only covers generated ids
+ALTER TABLE shorten_urls ALTER COLUMN id TYPE VARCHAR(32);
+ALTER TABLE shorten_urls ADD COLUMN IF NOT EXISTS shared BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE shorten_urls DROP CONSTRAINT IF EXISTS shorten_urls_url_key;
+CREATE UNIQUE INDEX IF NOT EXISTS shorten_urls_shared_url_key ON shorten_urls (url) WHERE shared;
@@ -0,0 +1,3 @@
+-- optional expiry per link, expired rows are purged in the background
+ALTER TABLE shorten_urls ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS shorten_urls_expires_at_idx ON shorten_urls (expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,10 @@
+-- one row per redirect, used for click analytics
+CREATE TABLE IF NOT EXISTS shorten_clicks (
+    id BIGSERIAL PRIMARY KEY,
+    link_id VARCHAR(32) NOT NULL REFERENCES shorten_urls (id) ON DELETE CASCADE,
+    clicked_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    referrer TEXT,
+    user_agent TEXT,
+    client_ip TEXT
+);
+CREATE INDEX IF NOT EXISTS shorten_clicks_link_id_idx ON shorten_clicks (link_id, clicked_at);
@@ -0,0 +1,2 @@
+-- hash of the secret token that allows the creator to retarget or delete a link
+ALTER TABLE shorten_urls ADD COLUMN IF NOT EXISTS token_hash TEXT;
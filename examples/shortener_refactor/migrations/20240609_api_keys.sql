@@ -0,0 +1,9 @@
+-- api keys guarding link creation, only their blake3 hash is stored
+CREATE TABLE IF NOT EXISTS api_keys (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    key_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ
+);
+ALTER TABLE shorten_urls ADD COLUMN IF NOT EXISTS api_key_id BIGINT REFERENCES api_keys (id);
@@ -0,0 +1,2 @@
+-- when a link was created, rows from before this migration get the time it ran
+ALTER TABLE shorten_urls ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,2 @@
+-- per-link redirect status, NULL follows DEFAULT_REDIRECT_STATUS
+ALTER TABLE shorten_urls ADD COLUMN IF NOT EXISTS redirect_status SMALLINT;
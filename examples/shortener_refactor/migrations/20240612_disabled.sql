@@ -0,0 +1,3 @@
+-- set by an admin to take a link down, redirects answer with this status instead
+ALTER TABLE shorten_urls ADD COLUMN IF NOT EXISTS disabled_status SMALLINT;
+ALTER TABLE shorten_urls ADD COLUMN IF NOT EXISTS disabled_reason TEXT;
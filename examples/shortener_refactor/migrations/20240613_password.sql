@@ -0,0 +1,2 @@
+-- argon2 hash in PHC format, visitors have to enter the password before being redirected
+ALTER TABLE shorten_urls ADD COLUMN IF NOT EXISTS password_hash TEXT;
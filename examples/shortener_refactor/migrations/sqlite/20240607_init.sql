@@ -0,0 +1,19 @@
+-- sqlite counterpart of the postgres migrations up to 20240607_clicks.sql
+CREATE TABLE IF NOT EXISTS shorten_urls (
+    id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    shared BOOLEAN NOT NULL DEFAULT TRUE,
+    expires_at TIMESTAMP
+);
+CREATE UNIQUE INDEX IF NOT EXISTS shorten_urls_shared_url_key ON shorten_urls (url) WHERE shared;
+CREATE INDEX IF NOT EXISTS shorten_urls_expires_at_idx ON shorten_urls (expires_at) WHERE expires_at IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS shorten_clicks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    link_id TEXT NOT NULL REFERENCES shorten_urls (id) ON DELETE CASCADE,
+    clicked_at TIMESTAMP NOT NULL,
+    referrer TEXT,
+    user_agent TEXT,
+    client_ip TEXT
+);
+CREATE INDEX IF NOT EXISTS shorten_clicks_link_id_idx ON shorten_clicks (link_id, clicked_at);
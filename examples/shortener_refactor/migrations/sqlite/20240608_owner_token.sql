@@ -0,0 +1 @@
+ALTER TABLE shorten_urls ADD COLUMN token_hash TEXT;
@@ -0,0 +1,2 @@
+-- sqlite cannot add a column with a non constant default, inserts set it instead
+ALTER TABLE shorten_urls ADD COLUMN created_at TIMESTAMP;
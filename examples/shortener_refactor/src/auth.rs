@@ -0,0 +1,57 @@
+use axum::{async_trait, extract::FromRequestParts};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use http::request::Parts;
+
+use crate::{token, ApiKey, AppState, ShortenError, ADMIN_TOKEN};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Extractor for routes that need an api key, sent in the `X-Api-Key` header.
+#[derive(Debug, Clone)]
+pub struct Authenticated(pub ApiKey);
+
+#[async_trait]
+impl FromRequestParts<AppState> for Authenticated {
+    type Rejection = ShortenError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(auth) = parts.extensions.get::<Self>() {
+            return Ok(auth.clone());
+        }
+        let key = parts
+            .headers
+            .get(API_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .ok_or(ShortenError::Unauthorized)?;
+        Ok(Self(state.authenticate(key).await?))
+    }
+}
+
+/// Extractor for admin routes, the `Authorization: Bearer` token has to match
+/// `ADMIN_TOKEN`. Nobody gets in while that is unset.
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = ShortenError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let admin_token = ADMIN_TOKEN.as_deref().ok_or(ShortenError::AdminRequired)?;
+        let TypedHeader(Authorization(bearer)) =
+            TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| ShortenError::AdminRequired)?;
+        if token::verify(bearer.token(), &token::hash(admin_token)) {
+            Ok(Self)
+        } else {
+            Err(ShortenError::AdminRequired)
+        }
+    }
+}
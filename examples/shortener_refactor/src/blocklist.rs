@@ -0,0 +1,142 @@
+use std::{collections::HashSet, net::IpAddr};
+
+use ipnet::IpNet;
+use url::{Host, Url};
+
+use crate::ShortenError;
+
+/// Targets that must never be shortened or reached through a link.
+const PRIVATE_TARGETS: &str = "
+localhost
+*.localhost
+0.0.0.0/8
+10.0.0.0/8
+100.64.0.0/10
+127.0.0.0/8
+169.254.0.0/16
+172.16.0.0/12
+192.168.0.0/16
+::1/128
+::/128
+fc00::/7
+fe80::/10
+";
+
+/// Hosts and networks targets may not point at. Entries are exact hosts
+/// (`evil.example`), wildcards matching every subdomain (`*.evil.example`) and
+/// addresses or CIDR ranges (`10.0.0.0/8`). Only hosts written as an address are
+/// matched against the ranges, names are not resolved.
+#[derive(Debug, Default, Clone)]
+pub struct Blocklist {
+    hosts: HashSet<String>,
+    // `.evil.example` for `*.evil.example`
+    suffixes: Vec<String>,
+    networks: Vec<IpNet>,
+}
+
+impl Blocklist {
+    /// One entry per line, blank lines and everything after a `#` are ignored.
+    pub fn parse(list: &str) -> Result<Self, ShortenError> {
+        Self::parse_lines(list).map_err(ShortenError::InvalidBlocklist)
+    }
+
+    /// Loopback, private, link-local and unspecified addresses plus `localhost`.
+    pub fn private_targets() -> Self {
+        Self::parse(PRIVATE_TARGETS).expect("built-in blocklist is valid")
+    }
+
+    /// Read the list at `path`, adding the private targets when `block_private` is set.
+    pub fn load(path: Option<&str>, block_private: bool) -> Result<Self, ShortenError> {
+        let mut blocklist = match path {
+            Some(path) => {
+                let list = std::fs::read_to_string(path)
+                    .map_err(|e| ShortenError::InvalidBlocklist(format!("{}: {}", path, e)))?;
+                Self::parse_lines(&list)
+                    .map_err(|e| ShortenError::InvalidBlocklist(format!("{}: {}", path, e)))?
+            }
+            None => Self::default(),
+        };
+        if block_private {
+            blocklist.extend(Self::private_targets());
+        }
+        Ok(blocklist)
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.len() + self.suffixes.len() + self.networks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fail with `ShortenError::BlockedUrl` when the host of `url` is on the list.
+    pub fn check(&self, url: &str) -> Result<(), ShortenError> {
+        let blocked = match Url::parse(url).ok().as_ref().and_then(Url::host) {
+            Some(Host::Domain(domain)) => self.blocks_domain(domain),
+            Some(Host::Ipv4(ip)) => self.blocks_ip(ip.into()),
+            Some(Host::Ipv6(ip)) => self.blocks_ip(ip.into()),
+            None => false,
+        };
+        if blocked {
+            Err(ShortenError::BlockedUrl(url.to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn parse_lines(list: &str) -> Result<Self, String> {
+        let mut blocklist = Self::default();
+        for (n, line) in list.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if !entry.is_empty() {
+                blocklist
+                    .add(entry)
+                    .map_err(|e| format!("line {}: {}", n + 1, e))?;
+            }
+        }
+        Ok(blocklist)
+    }
+
+    fn add(&mut self, entry: &str) -> Result<(), String> {
+        if let Ok(net) = entry.parse::<IpNet>() {
+            self.networks.push(net);
+        } else if let Ok(ip) = entry.parse::<IpAddr>() {
+            self.networks.push(ip.into());
+        } else if let Some(domain) = entry.strip_prefix("*.") {
+            self.suffixes
+                .push(format!(".{}", normalize_domain(domain)?));
+        } else {
+            self.hosts.insert(normalize_domain(entry)?);
+        }
+        Ok(())
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.hosts.extend(other.hosts);
+        self.suffixes.extend(other.suffixes);
+        self.networks.extend(other.networks);
+    }
+
+    fn blocks_domain(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.');
+        self.hosts.contains(domain) || self.suffixes.iter().any(|suffix| domain.ends_with(suffix))
+    }
+
+    fn blocks_ip(&self, ip: IpAddr) -> bool {
+        // ::ffff:127.0.0.1 is still loopback
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        self.networks.iter().any(|net| net.contains(&ip))
+    }
+}
+
+// lowercased and punycoded the way `Url` reports hosts
+fn normalize_domain(domain: &str) -> Result<String, String> {
+    match Host::parse(domain) {
+        Ok(Host::Domain(domain)) => Ok(domain.trim_end_matches('.').to_string()),
+        Ok(_) | Err(_) => Err(format!("{} is not a host, wildcard or network", domain)),
+    }
+}
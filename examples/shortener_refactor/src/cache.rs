@@ -0,0 +1,98 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+use tracing::debug;
+
+use crate::ShortenUrl;
+
+/// Bounded LRU cache in front of `Storage::get_url`. Misses are cached too (for a
+/// shorter time), so ids that do not exist cannot hammer the database either.
+#[derive(Debug)]
+pub struct UrlCache {
+    entries: Mutex<LruCache<String, CacheEntry>>,
+    ttl: Duration,
+    negative_ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    row: Option<ShortenUrl>,
+    cached_at: Instant,
+}
+
+impl UrlCache {
+    pub fn new(capacity: NonZeroUsize, ttl: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttl,
+            negative_ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// `None` when `id` is not cached, `Some(None)` when it is cached as missing.
+    pub fn get(&self, id: &str) -> Option<Option<ShortenUrl>> {
+        let cached = {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(id) {
+                Some(entry) if entry.cached_at.elapsed() < self.ttl_of(entry) => {
+                    Some(entry.row.clone())
+                }
+                Some(_) => {
+                    entries.pop(id);
+                    None
+                }
+                None => None,
+            }
+        };
+        if cached.is_some() {
+            let hits = self.hits.fetch_add(1, Ordering::Relaxed) + 1;
+            debug!(id, hits, misses = self.misses(), "url cache hit");
+        } else {
+            let misses = self.misses.fetch_add(1, Ordering::Relaxed) + 1;
+            debug!(id, hits = self.hits(), misses, "url cache miss");
+        }
+        cached
+    }
+
+    pub fn insert(&self, id: &str, row: Option<ShortenUrl>) {
+        let entry = CacheEntry {
+            row,
+            cached_at: Instant::now(),
+        };
+        self.entries.lock().unwrap().put(id.to_string(), entry);
+    }
+
+    pub fn invalidate(&self, id: &str) {
+        self.entries.lock().unwrap().pop(id);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn ttl_of(&self, entry: &CacheEntry) -> Duration {
+        match entry.row {
+            Some(_) => self.ttl,
+            None => self.negative_ttl,
+        }
+    }
+}
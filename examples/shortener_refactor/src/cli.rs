@@ -0,0 +1,304 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    pin::pin,
+};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use futures::TryStreamExt;
+
+use crate::{storage, AppState, ExportFormat, LinkFilter, ShortenUrl};
+
+/// Url shortener server and its maintenance commands.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the http server (the default)
+    Serve,
+    /// Manage the api keys allowed to create links
+    #[command(subcommand)]
+    ApiKey(ApiKeyCommand),
+    /// Inspect or apply the database migrations
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+    #[command(flatten)]
+    Link(LinkCommand),
+}
+
+/// Link management for support staff, checks are the ones the api applies.
+#[derive(Debug, Subcommand)]
+enum LinkCommand {
+    /// List links ordered by id, one page at a time
+    List(ListArgs),
+    /// Show everything stored about a link
+    Show { id: String },
+    /// Delete a link and its clicks
+    Delete { id: String },
+    /// Take a link down, visitors get the status instead of a redirect
+    Disable {
+        id: String,
+        /// 451 or 410
+        #[arg(long, default_value_t = 451)]
+        status: u16,
+        /// Why, kept for the admins
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Let a disabled link redirect again
+    Enable { id: String },
+    /// Point a link at another url
+    Retarget { id: String, url: String },
+    /// Write every link, to stdout unless a file is given
+    Export {
+        #[arg(long, short)]
+        output: Option<String>,
+        /// Defaults to csv for a *.csv output and json lines otherwise
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+    },
+    /// Read links written by export, keeping their ids. `-` reads stdin
+    Import {
+        input: String,
+        /// Defaults to csv for a *.csv input and json lines otherwise
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+        /// Only report what would be imported
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Args)]
+struct ListArgs {
+    /// Only links whose url contains this
+    #[arg(long)]
+    url: Option<String>,
+    #[arg(long)]
+    api_key_id: Option<i64>,
+    /// RFC 3339, e.g. 2024-06-01T00:00:00Z
+    #[arg(long)]
+    created_after: Option<DateTime<Utc>>,
+    #[arg(long)]
+    created_before: Option<DateTime<Utc>>,
+    /// Only links whose expiry has passed
+    #[arg(long)]
+    expired: bool,
+    /// Only disabled links
+    #[arg(long)]
+    disabled: bool,
+    /// Start after this id, as printed at the end of the previous page
+    #[arg(long)]
+    after: Option<String>,
+    #[arg(long, default_value_t = 50)]
+    limit: u32,
+}
+
+impl From<ListArgs> for LinkFilter {
+    fn from(args: ListArgs) -> Self {
+        Self {
+            url_contains: args.url,
+            api_key_id: args.api_key_id,
+            created_after: args.created_after,
+            created_before: args.created_before,
+            expired: args.expired,
+            disabled: args.disabled,
+            after: args.after,
+            limit: args.limit,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum ApiKeyCommand {
+    /// Create a key and print its secret, it cannot be shown again
+    Create { name: String },
+    /// Revoke the key with this name
+    Revoke { name: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum MigrateCommand {
+    /// Apply every pending migration
+    Run,
+    /// Show each migration and whether it was applied
+    List,
+    /// Fail when a migration is pending or was changed after being applied
+    Check,
+}
+
+impl Cli {
+    pub async fn execute(self) -> Result<()> {
+        match self.command.unwrap_or(Command::Serve) {
+            Command::Serve => crate::run().await,
+            Command::ApiKey(cmd) => {
+                let state = AppState::try_new().await?;
+                match cmd {
+                    ApiKeyCommand::Create { name } => {
+                        println!("{}", state.create_api_key(&name).await?)
+                    }
+                    ApiKeyCommand::Revoke { name } => state.revoke_api_key(&name).await?,
+                }
+                state.close().await;
+                Ok(())
+            }
+            Command::Migrate(cmd) => {
+                // connect without AppState, it would apply the migrations itself
+                let storage = storage::connect(&dotenvy::var("DATABASE_URL")?).await?;
+                match cmd {
+                    MigrateCommand::Run => storage.migrate().await?,
+                    MigrateCommand::List => {
+                        for m in storage.migrations().await? {
+                            let status = match (m.applied, m.modified) {
+                                (true, true) => "modified",
+                                (true, false) => "applied",
+                                _ => "pending",
+                            };
+                            println!("{} {:<8} {}", m.version, status, m.description);
+                        }
+                    }
+                    MigrateCommand::Check => {
+                        let migrations = storage.migrations().await?;
+                        let pending = migrations.iter().filter(|m| !m.applied).count();
+                        let modified = migrations.iter().filter(|m| m.modified).count();
+                        if pending > 0 || modified > 0 {
+                            bail!("{pending} pending and {modified} modified migrations");
+                        }
+                    }
+                }
+                storage.close().await;
+                Ok(())
+            }
+            Command::Link(cmd) => {
+                let state = AppState::try_new().await?;
+                let result = manage_links(&state, cmd).await;
+                state.close().await;
+                result
+            }
+        }
+    }
+}
+
+async fn manage_links(state: &AppState, cmd: LinkCommand) -> Result<()> {
+    match cmd {
+        LinkCommand::List(args) => {
+            let filter = LinkFilter::from(args);
+            let rows = state.list(&filter).await?;
+            for row in &rows {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    row.id,
+                    link_status(row),
+                    row.created_at
+                        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    row.url
+                );
+            }
+            match rows.last() {
+                Some(last) if rows.len() == filter.limit as usize => {
+                    eprintln!("more links may follow, continue with --after {}", last.id)
+                }
+                _ => {}
+            }
+        }
+        LinkCommand::Show { id } => {
+            let row = state.link(&id).await?;
+            let time = |t: Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339());
+            println!("id:              {}", row.id);
+            println!("url:             {}", row.url);
+            println!("status:          {}", link_status(&row));
+            println!("shared:          {}", row.shared);
+            println!(
+                "created_at:      {}",
+                time(row.created_at).unwrap_or_default()
+            );
+            println!(
+                "expires_at:      {}",
+                time(row.expires_at).unwrap_or_default()
+            );
+            println!("api_key_id:      {}", opt(row.api_key_id));
+            println!("redirect_status: {}", opt(row.redirect_status));
+            println!("disabled_reason: {}", opt(row.disabled_reason.as_ref()));
+            println!("password:        {}", row.password_hash.is_some());
+        }
+        LinkCommand::Delete { id } => state.admin_delete(&id).await?,
+        LinkCommand::Disable { id, status, reason } => {
+            state.disable(&id, status, reason.as_deref()).await?
+        }
+        LinkCommand::Enable { id } => state.enable(&id).await?,
+        LinkCommand::Retarget { id, url } => state.admin_retarget(&id, &url).await?,
+        LinkCommand::Export { output, format } => {
+            let format = format.unwrap_or_else(|| {
+                output
+                    .as_deref()
+                    .map(ExportFormat::from_path)
+                    .unwrap_or_default()
+            });
+            let mut out: Box<dyn Write> = match output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(BufWriter::new(io::stdout().lock())),
+            };
+            let mut chunks = pin!(state.export(format));
+            while let Some(chunk) = chunks.try_next().await? {
+                out.write_all(&chunk)?;
+            }
+            out.flush()?;
+        }
+        LinkCommand::Import {
+            input,
+            format,
+            dry_run,
+        } => {
+            let (data, format) = match input.as_str() {
+                "-" => {
+                    let mut data = Vec::new();
+                    io::stdin().lock().read_to_end(&mut data)?;
+                    (data, format.unwrap_or_default())
+                }
+                path => (
+                    std::fs::read(path)?,
+                    format.unwrap_or_else(|| ExportFormat::from_path(path)),
+                ),
+            };
+            let report = state.import(format, &data, dry_run).await?;
+            for failure in &report.failed {
+                eprintln!("row {}: {}", failure.row, failure.error);
+            }
+            let verb = if dry_run { "would import" } else { "imported" };
+            eprintln!(
+                "{} {} links, {} failed",
+                verb,
+                report.imported,
+                report.failed.len()
+            );
+            if !report.failed.is_empty() {
+                bail!("{} links could not be imported", report.failed.len());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn link_status(row: &ShortenUrl) -> String {
+    if let Some(status) = row.disabled_status {
+        format!("disabled({})", status)
+    } else if row.expires_at.is_some_and(|t| t <= Utc::now()) {
+        "expired".to_string()
+    } else if row.password_hash.is_some() {
+        "protected".to_string()
+    } else {
+        "active".to_string()
+    }
+}
+
+fn opt<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
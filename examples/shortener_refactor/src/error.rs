@@ -1,9 +1,24 @@
 use axum::{
-    extract::rejection::JsonRejection,
-    response::{Html, IntoResponse},
+    extract::{
+        rejection::{FormRejection, JsonRejection, QueryRejection},
+        Request,
+    },
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+    Json,
 };
-use http::StatusCode;
+use http::{
+    header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER},
+    HeaderMap, HeaderValue, StatusCode,
+};
+use nanoid::nanoid;
+use serde::Serialize;
 use thiserror::Error;
+use tracing::{error, info_span, Instrument};
+use utoipa::ToSchema;
+
+/// Echoed back on every response, generated when the client did not send one.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
 
 #[derive(Debug, Error)]
 pub enum ShortenError {
@@ -15,28 +30,252 @@ pub enum ShortenError {
     Notfound(String),
     #[error(transparent)]
     JsonRejectionError(#[from] JsonRejection),
+    #[error("invalid input: {1}")]
+    InvalidInput(StatusCode, String),
+    #[error("invalid alias: {0}")]
+    InvalidAlias(String),
+    #[error("alias: {0} is already taken")]
+    AliasTaken(String),
+    #[error("invalid expiry: {0}")]
+    InvalidExpiry(String),
+    #[error("id: {0} has expired")]
+    Expired(String),
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+    #[error("no free id found after {0} attempts")]
+    IdSpaceExhausted(u32),
+    #[error("a batch may contain at most {0} urls")]
+    BatchTooLarge(usize),
+    #[error("not allowed to manage id: {0}")]
+    Forbidden(String),
+    #[error("a valid api key is required")]
+    Unauthorized,
+    #[error("api key: {0} already exists")]
+    ApiKeyExists(String),
+    #[error("too many requests, retry after {0} seconds")]
+    RateLimited(u64),
+    #[error("invalid qr code option: {0}")]
+    InvalidQrOption(String),
+    #[error("redirect status {0} is not one of 301, 302, 307, 308")]
+    InvalidRedirectStatus(u16),
+    #[error("url: {0} is not allowed")]
+    BlockedUrl(String),
+    #[error("invalid blocklist: {0}")]
+    InvalidBlocklist(String),
+    #[error("id: {0} has been disabled")]
+    Disabled(String, u16),
+    #[error("disable status {0} is not one of 410, 451")]
+    InvalidDisableStatus(u16),
+    #[error("a valid admin token is required")]
+    AdminRequired,
+    #[error("invalid password: {0}")]
+    InvalidPassword(String),
+    #[error("id: {0} is password protected")]
+    PasswordRequired(String),
+    #[error("wrong password for id: {0}")]
+    WrongPassword(String),
+    #[error("too many wrong passwords, retry after {0} seconds")]
+    Locked(u64),
+    #[error("id: {0} is shared with other requesters and can no longer be managed")]
+    SharedLink(String),
+    #[error("url: {0} already has a shared link")]
+    SharedUrlTaken(String),
+    #[error("invalid row: {0}")]
+    InvalidRow(String),
+    #[error("export failed: {0}")]
+    ExportFailed(String),
+    #[error("unsupported database scheme: {0}")]
+    UnsupportedDatabase(String),
+    #[error("id: {0} is stored with an unusable url: {1:?}")]
+    UnusableStoredUrl(String, String),
+}
+
+impl From<QueryRejection> for ShortenError {
+    fn from(rejection: QueryRejection) -> Self {
+        ShortenError::InvalidInput(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<FormRejection> for ShortenError {
+    fn from(rejection: FormRejection) -> Self {
+        ShortenError::InvalidInput(rejection.status(), rejection.body_text())
+    }
+}
+
+impl ShortenError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ShortenError::EnvError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ShortenError::Notfound(_) => StatusCode::NOT_FOUND,
+            ShortenError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ShortenError::JsonRejectionError(json_rejection) => json_rejection.status(),
+            ShortenError::InvalidInput(status, _) => *status,
+            ShortenError::InvalidAlias(_) => StatusCode::BAD_REQUEST,
+            ShortenError::AliasTaken(_) => StatusCode::CONFLICT,
+            ShortenError::InvalidExpiry(_) => StatusCode::BAD_REQUEST,
+            ShortenError::Expired(_) => StatusCode::GONE,
+            ShortenError::InvalidUrl(_) => StatusCode::BAD_REQUEST,
+            ShortenError::IdSpaceExhausted(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ShortenError::BatchTooLarge(_) => StatusCode::BAD_REQUEST,
+            ShortenError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ShortenError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ShortenError::ApiKeyExists(_) => StatusCode::CONFLICT,
+            ShortenError::UnsupportedDatabase(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ShortenError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ShortenError::InvalidQrOption(_) => StatusCode::BAD_REQUEST,
+            ShortenError::InvalidRedirectStatus(_) => StatusCode::BAD_REQUEST,
+            ShortenError::BlockedUrl(_) => StatusCode::FORBIDDEN,
+            ShortenError::InvalidBlocklist(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ShortenError::Disabled(_, status) => {
+                StatusCode::from_u16(*status).unwrap_or(StatusCode::GONE)
+            }
+            ShortenError::InvalidDisableStatus(_) => StatusCode::BAD_REQUEST,
+            ShortenError::AdminRequired => StatusCode::UNAUTHORIZED,
+            ShortenError::InvalidPassword(_) => StatusCode::BAD_REQUEST,
+            ShortenError::PasswordRequired(_) => StatusCode::UNAUTHORIZED,
+            ShortenError::WrongPassword(_) => StatusCode::UNAUTHORIZED,
+            ShortenError::Locked(_) => StatusCode::TOO_MANY_REQUESTS,
+            ShortenError::SharedLink(_) => StatusCode::CONFLICT,
+            ShortenError::SharedUrlTaken(_) => StatusCode::CONFLICT,
+            ShortenError::InvalidRow(_) => StatusCode::BAD_REQUEST,
+            ShortenError::ExportFailed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ShortenError::UnusableStoredUrl(..) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable machine readable code, clients should match on this rather than the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ShortenError::EnvError(_)
+            | ShortenError::DatabaseError(_)
+            | ShortenError::UnsupportedDatabase(_)
+            | ShortenError::ExportFailed(_)
+            | ShortenError::UnusableStoredUrl(..) => "internal_error",
+            ShortenError::Notfound(_) => "not_found",
+            ShortenError::JsonRejectionError(_) => "invalid_json",
+            ShortenError::InvalidInput(..) => "invalid_input",
+            ShortenError::InvalidAlias(_) => "invalid_alias",
+            ShortenError::AliasTaken(_) => "alias_taken",
+            ShortenError::InvalidExpiry(_) => "invalid_expiry",
+            ShortenError::Expired(_) => "expired",
+            ShortenError::InvalidUrl(_) => "invalid_url",
+            ShortenError::IdSpaceExhausted(_) => "id_space_exhausted",
+            ShortenError::BatchTooLarge(_) => "batch_too_large",
+            ShortenError::Forbidden(_) => "forbidden",
+            ShortenError::Unauthorized => "unauthorized",
+            ShortenError::ApiKeyExists(_) => "api_key_exists",
+            ShortenError::RateLimited(_) => "rate_limited",
+            ShortenError::InvalidQrOption(_) => "invalid_qr_option",
+            ShortenError::InvalidRedirectStatus(_) => "invalid_redirect_status",
+            ShortenError::BlockedUrl(_) => "blocked_url",
+            ShortenError::InvalidBlocklist(_) => "invalid_blocklist",
+            ShortenError::Disabled(..) => "disabled",
+            ShortenError::InvalidDisableStatus(_) => "invalid_disable_status",
+            ShortenError::AdminRequired => "admin_required",
+            ShortenError::InvalidPassword(_) => "invalid_password",
+            ShortenError::PasswordRequired(_) => "password_required",
+            ShortenError::SharedLink(_) => "shared_link",
+            ShortenError::SharedUrlTaken(_) => "shared_url_taken",
+            ShortenError::InvalidRow(_) => "invalid_row",
+            ShortenError::WrongPassword(_) => "wrong_password",
+            ShortenError::Locked(_) => "locked",
+        }
+    }
+
+    /// What the client gets to see, internal failures are only detailed in the log.
+    pub fn message(&self) -> String {
+        match self {
+            ShortenError::EnvError(_)
+            | ShortenError::DatabaseError(_)
+            | ShortenError::UnsupportedDatabase(_)
+            | ShortenError::ExportFailed(_)
+            | ShortenError::UnusableStoredUrl(..) => "internal server error".to_string(),
+            ShortenError::Notfound(uri) => format!("{} not found!", uri),
+            ShortenError::JsonRejectionError(json_rejection) => json_rejection.body_text(),
+            e => e.to_string(),
+        }
+    }
 }
 
 impl IntoResponse for ShortenError {
     fn into_response(self) -> axum::response::Response {
-        match self {
-            ShortenError::EnvError(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Html(format!("<h1>{}</h1>", e)),
-            ),
-            ShortenError::Notfound(uri) => (
-                StatusCode::NOT_FOUND,
-                Html(format!("<h1>{} not found!</h1>", uri)),
-            ),
-            ShortenError::DatabaseError(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Html(format!("<h1>{}</h1>", e)),
-            ),
-            ShortenError::JsonRejectionError(json_rejection) => (
-                json_rejection.status(),
-                Html(format!("<h1>{}</h1>", json_rejection.body_text())),
-            ),
+        let status = self.status();
+        let code = self.code();
+        let message = self.message();
+        if code == "internal_error" {
+            error!("request failed: {:?}", self);
+        }
+        let mut res = (status, Html(format!("<h1>{}</h1>", message))).into_response();
+        if let ShortenError::RateLimited(secs) | ShortenError::Locked(secs) = self {
+            res.headers_mut().insert(RETRY_AFTER, secs.into());
         }
-        .into_response()
+        // picked up by `negotiate_errors` for clients asking for json
+        res.extensions_mut().insert(ErrorBody { code, message });
+        res
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+}
+
+/// Error body for clients asking for json, everyone else gets the message as html.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct JsonError {
+    /// One of the `ShortenError` codes, e.g. `not_found` or `alias_taken`.
+    code: &'static str,
+    message: String,
+    request_id: String,
+}
+
+/// Middleware tagging every response with a request id and turning error pages into
+/// json for clients that prefer it. Handlers run inside a span carrying the id.
+pub async fn negotiate_errors(req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 64)
+        .map(str::to_string)
+        .unwrap_or_else(|| nanoid!());
+    let json = wants_json(req.headers());
+    let mut res = next
+        .run(req)
+        .instrument(info_span!("request", id = %request_id))
+        .await;
+    if let Some(ErrorBody { code, message }) = res.extensions_mut().remove::<ErrorBody>() {
+        if json {
+            let body = JsonError {
+                code,
+                message,
+                request_id: request_id.clone(),
+            };
+            let mut json_res = (res.status(), Json(body)).into_response();
+            for (name, value) in res.headers() {
+                if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+                    json_res.headers_mut().append(name, value.clone());
+                }
+            }
+            res = json_res;
+        }
+    }
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    res
+}
+
+// json only when it is asked for ahead of html, browsers and bare clients get html
+fn wants_json(headers: &HeaderMap) -> bool {
+    let accept = headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    match (accept.find("application/json"), accept.find("text/html")) {
+        (Some(json), Some(html)) => json < html,
+        (Some(_), None) => true,
+        _ => false,
     }
 }
@@ -0,0 +1,194 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    validate::{is_reserved, ALIAS_MAX_LEN, ALIAS_MIN_LEN},
+    ID_ALPHABET, ID_GROWTH_THRESHOLD, ID_LENGTH, ID_MAX_RETRIES,
+};
+
+/// Ids never grow past the longest alias we accept.
+const MAX_ID_LENGTH: usize = ALIAS_MAX_LEN;
+/// Number of insert attempts the collision rate is measured over.
+const COLLISION_WINDOW: u32 = 100;
+
+/// Generates random ids and grows their length once too many of them collide.
+#[derive(Debug)]
+pub struct IdGenerator {
+    alphabet: Vec<char>,
+    length: AtomicUsize,
+    max_retries: u32,
+    growth_threshold: f64,
+    // random bytes ids are drawn from
+    random: fn(usize) -> Vec<u8>,
+    // (attempts, collisions) in the current window
+    window: Mutex<(u32, u32)>,
+}
+
+impl IdGenerator {
+    pub fn new(length: usize, alphabet: &str, max_retries: u32, growth_threshold: f64) -> Self {
+        Self {
+            alphabet: alphabet.chars().collect(),
+            length: AtomicUsize::new(length.clamp(ALIAS_MIN_LEN, MAX_ID_LENGTH)),
+            max_retries: max_retries.max(1),
+            growth_threshold,
+            random: nanoid::rngs::default,
+            window: Mutex::new((0, 0)),
+        }
+    }
+
+    #[cfg(test)]
+    fn with_random(self, random: fn(usize) -> Vec<u8>) -> Self {
+        Self { random, ..self }
+    }
+
+    /// Build a generator from `ID_LENGTH`, `ID_ALPHABET`, `ID_MAX_RETRIES` and
+    /// `ID_GROWTH_THRESHOLD`; an alphabet that would produce unusable ids falls back
+    /// to the nanoid default.
+    pub fn from_env() -> Self {
+        let alphabet = match ID_ALPHABET.as_deref() {
+            Some(alphabet) if is_valid_alphabet(alphabet) => alphabet.to_string(),
+            Some(alphabet) => {
+                warn!("invalid ID_ALPHABET {:?}, using the default", alphabet);
+                nanoid::alphabet::SAFE.iter().collect()
+            }
+            None => nanoid::alphabet::SAFE.iter().collect(),
+        };
+        Self::new(*ID_LENGTH, &alphabet, *ID_MAX_RETRIES, *ID_GROWTH_THRESHOLD)
+    }
+
+    pub fn length(&self) -> usize {
+        self.length.load(Ordering::Relaxed)
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// A random id of the current length that is not a reserved word.
+    pub fn generate(&self) -> String {
+        loop {
+            let id = nanoid::format(self.random, &self.alphabet, self.length());
+            if !is_reserved(&id) {
+                break id;
+            }
+        }
+    }
+
+    /// Record the outcome of an insert attempt, growing the id length when the
+    /// collision rate of the last window exceeded the threshold.
+    pub fn record(&self, collided: bool) {
+        let mut window = self.window.lock().unwrap();
+        window.0 += 1;
+        if collided {
+            window.1 += 1;
+        }
+        if window.0 < COLLISION_WINDOW {
+            return;
+        }
+        let rate = window.1 as f64 / window.0 as f64;
+        *window = (0, 0);
+        if rate > self.growth_threshold {
+            let length = self.length();
+            if length < MAX_ID_LENGTH {
+                self.length.store(length + 1, Ordering::Relaxed);
+                info!(
+                    "id collision rate {:.2} exceeded {:.2}, growing ids to {} characters",
+                    rate,
+                    self.growth_threshold,
+                    length + 1
+                );
+            }
+        }
+    }
+}
+
+/// Check a configured `ID_LENGTH`. Ids are imported like aliases, so they need an
+/// alias length to survive an export and import.
+pub fn validate_id_length(length: usize) -> Result<(), String> {
+    if (ALIAS_MIN_LEN..=MAX_ID_LENGTH).contains(&length) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} is not between {} and {}",
+            length, ALIAS_MIN_LEN, MAX_ID_LENGTH
+        ))
+    }
+}
+
+// ids end up in paths and aliases, so stick to the alias charset
+fn is_valid_alphabet(alphabet: &str) -> bool {
+    let mut chars: Vec<char> = alphabet.chars().collect();
+    chars.sort_unstable();
+    chars.dedup();
+    chars.len() >= 2
+        && chars.len() == alphabet.chars().count()
+        && chars
+            .iter()
+            .all(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    // the first character of the alphabet, over and over, so every id collides
+    fn repeating(size: usize) -> Vec<u8> {
+        vec![0; size]
+    }
+
+    #[test]
+    fn ids_should_grow_once_collisions_pass_the_threshold() {
+        let ids = IdGenerator::new(4, "ab", 8, 0.1).with_random(repeating);
+        for attempt in 0..COLLISION_WINDOW {
+            ids.record(attempt < 10);
+        }
+        assert_eq!(ids.length(), 4, "10% does not exceed the threshold");
+
+        let mut taken = HashSet::new();
+        for _ in 0..COLLISION_WINDOW {
+            let id = ids.generate();
+            ids.record(!taken.insert(id));
+        }
+        assert_eq!(ids.length(), 5);
+        let id = ids.generate();
+        assert_eq!(id, "aaaaa");
+        assert!(!taken.contains(&id));
+    }
+
+    #[test]
+    fn ids_should_not_grow_past_the_longest_alias() {
+        let ids = IdGenerator::new(MAX_ID_LENGTH, "ab", 8, 0.1).with_random(repeating);
+        for _ in 0..COLLISION_WINDOW {
+            ids.record(true);
+        }
+        assert_eq!(ids.length(), MAX_ID_LENGTH);
+    }
+
+    #[test]
+    fn id_lengths_should_be_alias_lengths() {
+        assert!(validate_id_length(2).is_err());
+        assert!(validate_id_length(33).is_err());
+        assert!(validate_id_length(3).is_ok() && validate_id_length(32).is_ok());
+        assert_eq!(IdGenerator::new(1, "ab", 8, 0.1).length(), 3);
+    }
+
+    #[test]
+    fn reserved_ids_should_be_skipped() {
+        // spells "admin" first, then only "b"s
+        fn admin_first(size: usize) -> Vec<u8> {
+            static CALLS: AtomicUsize = AtomicUsize::new(0);
+            match CALLS.fetch_add(1, Ordering::Relaxed) {
+                0 => (0..size as u8).collect(),
+                _ => vec![5; size],
+            }
+        }
+        let ids = IdGenerator::new(5, "adminb", 8, 0.1).with_random(admin_first);
+        assert_eq!(ids.generate(), "bbbbb");
+    }
+}
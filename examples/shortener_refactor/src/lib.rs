@@ -1,11 +1,79 @@
+mod auth;
+mod blocklist;
+mod cache;
+mod cli;
 mod error;
+mod id;
+mod lockout;
+mod metrics;
+mod openapi;
+mod password;
+mod preview;
+mod public_url;
+mod qr;
+mod ratelimit;
 mod server;
 mod state;
+mod storage;
+mod token;
+mod transfer;
+mod validate;
 
-pub use error::ShortenError;
-pub use server::{run, ShortenRequest, ShortenResponse};
-pub use state::AppState;
+pub use auth::{Admin, Authenticated, API_KEY_HEADER};
+pub use blocklist::Blocklist;
+pub use cache::UrlCache;
+pub use cli::Cli;
+pub use error::{ShortenError, REQUEST_ID_HEADER};
+pub use metrics::Metrics;
+pub use openapi::ApiDoc;
+pub use public_url::{BaseUrl, ClientIp};
+pub use qr::{QrFormat, QrQuery};
+pub use ratelimit::RateLimiter;
+pub use server::{
+    app, routes, run, BatchResult, BlocklistReloaded, DisableRequest, RetargetRequest,
+    ShortenRequest, ShortenResponse, UnlockForm,
+};
+pub use state::{
+    AppState, Click, DailyClicks, LinkInfo, LinkOptions, LinkStats, Readiness, Shortened,
+};
+pub use storage::{
+    ApiKey, Imported, LinkFilter, MemoryStorage, MigrationStatus, NewUrl, PgStorage, PoolStats,
+    ShortenUrl, SqliteStorage, Storage,
+};
+pub use transfer::{ExportFormat, ImportFailure, ImportReport};
 
 lazy_static::lazy_static! {
     pub static ref LISTEN_ADDR: String = dotenvy::var("LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:8888".to_string());
+    // e.g. https://sho.rt or https://example.com/s, anything but an http(s) url with a host stops `run`
+    pub static ref PUBLIC_BASE_URL: Option<url::Url> = dotenvy::var("PUBLIC_BASE_URL").ok().filter(|v| !v.is_empty()).and_then(|v| public_url::parse_base_url(&v).ok());
+    // comma separated addresses or networks allowed to set X-Forwarded-For/Host/Proto
+    pub static ref TRUSTED_PROXIES: Vec<ipnet::IpNet> = dotenvy::var("TRUSTED_PROXIES").map(|v| public_url::parse_networks(&v)).unwrap_or_default();
+    // how long /readyz fails before the listener closes on shutdown
+    pub static ref SHUTDOWN_DELAY_SECS: u64 = dotenvy::var("SHUTDOWN_DELAY_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(5);
+    pub static ref SHUTDOWN_DRAIN_SECS: u64 = dotenvy::var("SHUTDOWN_DRAIN_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30);
+    pub static ref PURGE_INTERVAL_SECS: u64 = dotenvy::var("PURGE_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60);
+    pub static ref MAX_BATCH_SIZE: usize = dotenvy::var("MAX_BATCH_SIZE").ok().and_then(|v| v.parse().ok()).unwrap_or(100);
+    // largest body POST /admin/import accepts
+    pub static ref IMPORT_MAX_BYTES: usize = dotenvy::var("IMPORT_MAX_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(64 * 1024 * 1024);
+    // 3 to 32, the lengths aliases may have
+    pub static ref ID_LENGTH: usize = dotenvy::var("ID_LENGTH").ok().and_then(|v| v.parse().ok()).unwrap_or(6);
+    pub static ref ID_ALPHABET: Option<String> = dotenvy::var("ID_ALPHABET").ok();
+    pub static ref ID_MAX_RETRIES: u32 = dotenvy::var("ID_MAX_RETRIES").ok().and_then(|v| v.parse().ok()).unwrap_or(8);
+    pub static ref ID_GROWTH_THRESHOLD: f64 = dotenvy::var("ID_GROWTH_THRESHOLD").ok().and_then(|v| v.parse().ok()).unwrap_or(0.1);
+    pub static ref URL_CACHE_CAPACITY: usize = dotenvy::var("URL_CACHE_CAPACITY").ok().and_then(|v| v.parse().ok()).unwrap_or(10_000);
+    pub static ref URL_CACHE_TTL_SECS: u64 = dotenvy::var("URL_CACHE_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60);
+    pub static ref URL_CACHE_NEGATIVE_TTL_SECS: u64 = dotenvy::var("URL_CACHE_NEGATIVE_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(5);
+    // used by links created without a redirect_status, 308 when unset or not a redirect
+    pub static ref DEFAULT_REDIRECT_STATUS: u16 = dotenvy::var("DEFAULT_REDIRECT_STATUS").ok().and_then(|v| v.parse().ok()).filter(|status| validate::REDIRECT_STATUSES.contains(status)).unwrap_or(308);
+    pub static ref SKIP_MIGRATIONS: bool = dotenvy::var("SKIP_MIGRATIONS").ok().and_then(|v| v.parse().ok()).unwrap_or(false);
+    pub static ref CREATE_RATE_PER_MIN: u32 = dotenvy::var("CREATE_RATE_PER_MIN").ok().and_then(|v| v.parse().ok()).unwrap_or(30);
+    pub static ref REDIRECT_RATE_PER_MIN: u32 = dotenvy::var("REDIRECT_RATE_PER_MIN").ok().and_then(|v| v.parse().ok()).unwrap_or(600);
+    // one host, *.wildcard or network per line, re-read on SIGHUP and POST /admin/blocklist/reload
+    pub static ref BLOCKLIST_FILE: Option<String> = dotenvy::var("BLOCKLIST_FILE").ok().filter(|v| !v.is_empty());
+    pub static ref BLOCK_PRIVATE_TARGETS: bool = dotenvy::var("BLOCK_PRIVATE_TARGETS").ok().and_then(|v| v.parse().ok()).unwrap_or(true);
+    // password attempts without a success before a protected link refuses more for UNLOCK_LOCKOUT_SECS
+    pub static ref UNLOCK_MAX_FAILURES: u32 = dotenvy::var("UNLOCK_MAX_FAILURES").ok().and_then(|v| v.parse().ok()).unwrap_or(5);
+    pub static ref UNLOCK_LOCKOUT_SECS: u64 = dotenvy::var("UNLOCK_LOCKOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(300);
+    // admin routes answer 401 to everyone while unset
+    pub static ref ADMIN_TOKEN: Option<String> = dotenvy::var("ADMIN_TOKEN").ok().filter(|v| !v.is_empty());
 }
@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+/// Counts password attempts per link until one succeeds. After `max_failures` of them
+/// the link refuses further attempts for `lockout`, whoever makes them. Attempts are
+/// counted before the password is checked, so concurrent guesses cannot slip past the
+/// limit while the hashes are being verified.
+#[derive(Debug)]
+pub struct Lockout {
+    max_failures: u32,
+    lockout: Duration,
+    ids: DashMap<String, Failures>,
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Lockout {
+    pub fn new(max_failures: u32, lockout: Duration) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            lockout,
+            ids: DashMap::new(),
+        }
+    }
+
+    /// Count an attempt on `id`, or tell how long it stays locked. The attempt counts as
+    /// failed until `succeed` is called.
+    pub fn try_begin(&self, id: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut failures = self.ids.entry(id.to_string()).or_insert(Failures {
+            count: 0,
+            last: now,
+        });
+        // a finished lockout or a long pause starts the count over
+        let elapsed = now.duration_since(failures.last);
+        if elapsed >= self.lockout {
+            failures.count = 0;
+        }
+        if failures.count >= self.max_failures {
+            return Err(self.lockout - elapsed);
+        }
+        failures.count += 1;
+        failures.last = now;
+        Ok(())
+    }
+
+    pub fn succeed(&self, id: &str) {
+        self.ids.remove(id);
+    }
+
+    /// Forget links whose last failure is older than the lockout.
+    pub fn prune(&self) {
+        self.ids
+            .retain(|_, failures| failures.last.elapsed() < self.lockout);
+    }
+}
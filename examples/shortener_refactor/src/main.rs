@@ -1,13 +1,19 @@
 use anyhow::Result;
 
+use clap::Parser;
+use shortener_refactor::Cli;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // init tracing
-    let layer = fmt::layer().pretty().with_filter(LevelFilter::INFO);
+    // stdout is left to the commands, e.g. export
+    let layer = fmt::layer()
+        .pretty()
+        .with_writer(std::io::stderr)
+        .with_filter(LevelFilter::INFO);
     tracing_subscriber::registry().with(layer).init();
 
-    shortener_refactor::run().await
+    Cli::parse().execute().await
 }
@@ -0,0 +1,131 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::{AppState, PoolStats};
+
+/// Prometheus metrics of the shortener, rendered by `GET /metrics`.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    redirects: IntCounterVec,
+    pub(crate) id_collisions: IntCounter,
+    id_length: IntGauge,
+    pool_size: IntGauge,
+    pool_idle: IntGauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("shortener".to_string()), None)
+            .expect("prefix is a valid metric name");
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests handled, per route"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to produce a response, per route",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let redirects = IntCounterVec::new(
+            Opts::new("redirects_total", "Lookups of short ids to redirect"),
+            &["result"],
+        )
+        .unwrap();
+        let id_collisions = IntCounter::new(
+            "id_collisions_total",
+            "Generated ids that were already taken and had to be retried",
+        )
+        .unwrap();
+        let id_length = IntGauge::new("id_length", "Length of newly generated ids").unwrap();
+        let pool_size = IntGauge::new("db_pool_connections", "Open database connections").unwrap();
+        let pool_idle =
+            IntGauge::new("db_pool_idle_connections", "Idle database connections").unwrap();
+        for collector in [
+            Box::new(requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(latency.clone()),
+            Box::new(redirects.clone()),
+            Box::new(id_collisions.clone()),
+            Box::new(id_length.clone()),
+            Box::new(pool_size.clone()),
+            Box::new(pool_idle.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+        Self {
+            registry,
+            requests,
+            latency,
+            redirects,
+            id_collisions,
+            id_length,
+            pool_size,
+            pool_idle,
+        }
+    }
+
+    /// `result` is one of `hit`, `miss`, `expired`, `disabled` or `protected`.
+    pub fn record_redirect(&self, result: &str) {
+        self.redirects.with_label_values(&[result]).inc();
+    }
+
+    /// Text exposition format, gauges are refreshed from the given values first.
+    pub fn render(&self, id_length: usize, pool: Option<PoolStats>) -> String {
+        self.id_length.set(id_length as i64);
+        if let Some(pool) = pool {
+            self.pool_size.set(i64::from(pool.size));
+            self.pool_idle.set(pool.idle as i64);
+        }
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .expect("text encoding into memory");
+        String::from_utf8(buf).expect("text encoding is utf-8")
+    }
+}
+
+/// Middleware counting and timing every request by its route pattern, so ids do not
+/// end up as label values.
+pub async fn track_metrics(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "fallback".to_string());
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let res = next.run(req).await;
+    let metrics = state.metrics();
+    metrics
+        .latency
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    metrics
+        .requests
+        .with_label_values(&[&method, &route, res.status().as_str()])
+        .inc();
+    res
+}
@@ -0,0 +1,85 @@
+use utoipa::{
+    openapi::security::{
+        ApiKey as ApiKeyScheme, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    error::JsonError, server, BatchResult, BlocklistReloaded, DailyClicks, DisableRequest,
+    ExportFormat, ImportFailure, ImportReport, LinkInfo, LinkStats, PoolStats, Readiness,
+    RetargetRequest, ShortenRequest, ShortenResponse, UnlockForm, API_KEY_HEADER,
+};
+
+/// OpenAPI 3 description of every route `server::app` serves, except the docs themselves.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "shortener", description = "Url shortener with aliases, expiry and click stats."),
+    paths(
+        server::shortener_handler,
+        server::batch_handler,
+        server::health_handler,
+        server::ready_handler,
+        server::metrics_handler,
+        server::redirect_handler,
+        server::unlock_handler,
+        server::retarget_handler,
+        server::delete_handler,
+        server::info_handler,
+        server::stats_handler,
+        server::qr_handler,
+        server::disable_handler,
+        server::enable_handler,
+        server::reload_blocklist_handler,
+        server::export_handler,
+        server::import_handler,
+    ),
+    components(schemas(
+        ShortenRequest,
+        ShortenResponse,
+        RetargetRequest,
+        BatchResult,
+        LinkInfo,
+        LinkStats,
+        DailyClicks,
+        Readiness,
+        PoolStats,
+        UnlockForm,
+        DisableRequest,
+        BlocklistReloaded,
+        ExportFormat,
+        ImportReport,
+        ImportFailure,
+        JsonError,
+    )),
+    modifiers(&Security)
+)]
+pub struct ApiDoc;
+
+/// Creating links takes an api key, managing one takes the token returned on creation
+/// and the admin routes take `ADMIN_TOKEN`.
+struct Security;
+
+impl Modify for Security {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKeyScheme::Header(ApiKeyValue::new(API_KEY_HEADER))),
+        );
+        components.add_security_scheme(
+            "owner_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Swagger UI page rendering `/openapi.json`.
+pub const DOCS_PAGE: &str = include_str!("docs.html");
+/// Swagger UI 5.17.14, vendored so the docs work without internet access.
+pub const SWAGGER_UI_JS: &str = include_str!("../assets/swagger-ui/swagger-ui-bundle.js");
+pub const SWAGGER_UI_CSS: &str = include_str!("../assets/swagger-ui/swagger-ui.css");
@@ -0,0 +1,51 @@
+//! Passwords chosen by link creators. Unlike tokens they may be weak, so they are
+//! stored as salted argon2 hashes.
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+use crate::ShortenError;
+
+const PASSWORD_MAX_LEN: usize = 128;
+
+fn validate(password: &str) -> Result<(), ShortenError> {
+    if password.is_empty() || password.len() > PASSWORD_MAX_LEN {
+        return Err(ShortenError::InvalidPassword(format!(
+            "password must be between 1 and {} characters",
+            PASSWORD_MAX_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Validate and hash in PHC string format, salt and parameters included. Hashing is
+/// slow on purpose, so it runs on the blocking pool.
+pub(crate) async fn hash(password: &str) -> Result<String, ShortenError> {
+    validate(password)?;
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| ShortenError::InvalidPassword(e.to_string()))
+    })
+    .await
+    .expect("password hashing panicked")
+}
+
+/// Whether `password` matches `hash`, a malformed hash matches nothing.
+pub(crate) async fn verify(password: &str, hash: &str) -> bool {
+    let (password, hash) = (password.to_string(), hash.to_string());
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .expect("password verification panicked")
+}
@@ -0,0 +1,87 @@
+use crate::{validate::normalize_url, LinkInfo};
+
+/// Page shown for `/:id+`, the target is only followed when the visitor clicks it.
+pub fn render(info: &LinkInfo) -> String {
+    // rows stored before urls were validated may point anywhere, javascript: included,
+    // those are shown but not linked
+    let target = match normalize_url(&info.url) {
+        Ok(_) => format!(
+            r#"<a href="{url}" rel="noopener noreferrer nofollow">{url}</a>"#,
+            url = escape(&info.url)
+        ),
+        Err(_) => escape(&info.url),
+    };
+    let time = |t: Option<chrono::DateTime<chrono::Utc>>, none: &str| {
+        t.map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_else(|| none.to_string())
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="robots" content="noindex">
+<title>Preview of {id}</title>
+</head>
+<body>
+<h1>{id} points to</h1>
+<p>{target}</p>
+<ul>
+<li>Created: {created}</li>
+<li>Expires: {expires}</li>
+<li>Clicks: {clicks}</li>
+</ul>
+</body>
+</html>
+"#,
+        id = escape(&info.id),
+        target = target,
+        created = time(info.created_at, "unknown"),
+        expires = time(info.expires_at, "never"),
+        clicks = info.clicks,
+    )
+}
+
+/// Page shown instead of redirecting to a password protected link at `link`, `error`
+/// explains why the last attempt failed.
+pub fn password_form(id: &str, link: &str, error: Option<&str>) -> String {
+    let id = escape(id);
+    // absolute, the form is also shown again at /{id}/unlock after a wrong password
+    let action = escape(&format!("{}/unlock", link));
+    let error = error
+        .map(|e| format!("<p role=\"alert\">{}</p>\n", escape(e)))
+        .unwrap_or_default();
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="robots" content="noindex">
+<title>{id} is password protected</title>
+</head>
+<body>
+<h1>{id} is password protected</h1>
+{error}<form method="post" action="{action}">
+<label>Password <input type="password" name="password" required autofocus></label>
+<button type="submit">Continue</button>
+</form>
+</body>
+</html>
+"#
+    )
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,239 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    async_trait,
+    extract::{rejection::ExtensionRejection, ConnectInfo, FromRequestParts},
+};
+use http::{header::HOST, request::Parts};
+use ipnet::IpNet;
+use url::Url;
+
+use crate::{LISTEN_ADDR, PUBLIC_BASE_URL, TRUSTED_PROXIES};
+
+const FORWARDED_FOR: &str = "x-forwarded-for";
+const FORWARDED_HOST: &str = "x-forwarded-host";
+const FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// Where short links are reachable from the outside, without a trailing slash.
+///
+/// `PUBLIC_BASE_URL` is used when set, its scheme and host are replaced by
+/// `X-Forwarded-Proto` and `X-Forwarded-Host` when the request comes from one of the
+/// `TRUSTED_PROXIES`. Without a configured base the `Host` header is used.
+#[derive(Debug, Clone)]
+pub struct BaseUrl(String);
+
+impl BaseUrl {
+    pub fn link(&self, id: &str) -> String {
+        format!("{}/{}", self.0, id)
+    }
+
+    // `public` and `proxies` are `PUBLIC_BASE_URL` and `TRUSTED_PROXIES` outside of tests
+    fn resolve(parts: &Parts, public: Option<&Url>, proxies: &[IpNet]) -> Self {
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                // proxies append to the header, the first one saw the client
+                .and_then(|v| v.split(',').next())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+        let trusted = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .is_some_and(|ConnectInfo(addr)| is_trusted(addr.ip(), proxies));
+
+        let mut base = public.cloned().unwrap_or_else(|| {
+            let host = header(HOST.as_str()).unwrap_or(&LISTEN_ADDR);
+            Url::parse(&format!("http://{}", host))
+                .unwrap_or_else(|_| Url::parse(&format!("http://{}", &*LISTEN_ADDR)).unwrap())
+        });
+        if trusted {
+            if let Some(proto) = header(FORWARDED_PROTO).filter(|p| *p == "http" || *p == "https") {
+                // only fails when switching to a non special scheme, which is filtered out
+                let _ = base.set_scheme(proto);
+            }
+            if let Some(host) = header(FORWARDED_HOST) {
+                if let Ok(forwarded) = Url::parse(&format!("{}://{}", base.scheme(), host)) {
+                    let _ = base.set_host(forwarded.host_str());
+                    let _ = base.set_port(forwarded.port());
+                }
+            }
+        }
+        Self(base.as_str().trim_end_matches('/').to_string())
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for BaseUrl {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::resolve(
+            parts,
+            PUBLIC_BASE_URL.as_ref(),
+            &TRUSTED_PROXIES,
+        ))
+    }
+}
+
+/// Address of the client. Requests from one of the `TRUSTED_PROXIES` are attributed to
+/// the address the proxies saw, taken from `X-Forwarded-For`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    fn resolve(parts: &Parts, peer: IpAddr, proxies: &[IpNet]) -> Self {
+        if !is_trusted(peer, proxies) {
+            return Self(peer);
+        }
+        // every proxy appends the address it saw, so the client is the last one that is
+        // not a proxy of ours; anything before it may have been sent by the client itself
+        let forwarded: Vec<IpAddr> = parts
+            .headers
+            .get_all(FORWARDED_FOR)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|ip| ip.trim().parse().ok())
+            .collect();
+        let client = forwarded
+            .into_iter()
+            .rev()
+            .find(|ip| !is_trusted(*ip, proxies))
+            .unwrap_or(peer);
+        Self(client)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = ExtensionRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(peer) = ConnectInfo::<SocketAddr>::from_request_parts(parts, state).await?;
+        Ok(Self::resolve(parts, peer.ip(), &TRUSTED_PROXIES))
+    }
+}
+
+fn is_trusted(ip: IpAddr, proxies: &[IpNet]) -> bool {
+    proxies.iter().any(|net| net.contains(&ip))
+}
+
+/// Parse a `PUBLIC_BASE_URL`, short links can only be built on an http(s) url with a host.
+pub fn parse_base_url(value: &str) -> Result<Url, String> {
+    let url = Url::parse(value).map_err(|e| format!("{}: {}", value, e))?;
+    if !matches!(url.scheme(), "http" | "https") || !url.has_host() {
+        return Err(format!("{}: not an http(s) url with a host", value));
+    }
+    Ok(url)
+}
+
+/// Parse a comma separated list of networks, plain addresses count as a network of one.
+/// Entries that are neither are skipped.
+pub fn parse_networks(list: &str) -> Vec<IpNet> {
+    list.split(',')
+        .map(str::trim)
+        .filter_map(|net| {
+            net.parse()
+                .ok()
+                .or_else(|| net.parse::<IpAddr>().ok().map(IpNet::from))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Request;
+
+    use super::*;
+
+    const PROXY: &str = "10.0.0.1:443";
+    const CLIENT: &str = "203.0.113.7:50000";
+
+    fn parts(peer: &str, headers: &[(&str, &str)]) -> Parts {
+        let mut req = Request::get("/").header(HOST, "sho.rt");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let addr: SocketAddr = peer.parse().unwrap();
+        req.extension(ConnectInfo(addr))
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    }
+
+    fn forwarded(peer: &str) -> Parts {
+        parts(
+            peer,
+            &[
+                (FORWARDED_PROTO, "https"),
+                (FORWARDED_HOST, "links.example.com, 10.0.0.1"),
+            ],
+        )
+    }
+
+    #[test]
+    fn host_header_should_be_used_without_a_configured_base() {
+        let base = BaseUrl::resolve(&parts(CLIENT, &[]), None, &[]);
+        assert_eq!(base.link("abc"), "http://sho.rt/abc");
+    }
+
+    #[test]
+    fn forwarded_headers_should_only_be_used_from_trusted_proxies() {
+        let proxies = parse_networks("127.0.0.1, 10.0.0.0/8");
+        let base = BaseUrl::resolve(&forwarded(PROXY), None, &proxies);
+        assert_eq!(base.link("abc"), "https://links.example.com/abc");
+
+        let base = BaseUrl::resolve(&forwarded(CLIENT), None, &proxies);
+        assert_eq!(base.link("abc"), "http://sho.rt/abc");
+        let base = BaseUrl::resolve(&forwarded(PROXY), None, &[]);
+        assert_eq!(base.link("abc"), "http://sho.rt/abc");
+    }
+
+    #[test]
+    fn public_base_url_should_override_the_host_header() {
+        let public = parse_base_url("https://example.com/s/").unwrap();
+        let base = BaseUrl::resolve(&parts(CLIENT, &[]), Some(&public), &[]);
+        assert_eq!(base.link("abc"), "https://example.com/s/abc");
+
+        // a trusted proxy still picks scheme and host, the path stays
+        let proxies = parse_networks("10.0.0.0/8");
+        let base = BaseUrl::resolve(&forwarded(PROXY), Some(&public), &proxies);
+        assert_eq!(base.link("abc"), "https://links.example.com/s/abc");
+    }
+
+    #[test]
+    fn client_ip_should_only_be_forwarded_by_trusted_proxies() {
+        let proxies = parse_networks("10.0.0.0/8");
+        let headers = [(FORWARDED_FOR, "198.51.100.1, 192.0.2.9, 10.0.0.2")];
+        let client = |peer: &str, proxies: &[IpNet]| {
+            let ip = peer.parse::<SocketAddr>().unwrap().ip();
+            ClientIp::resolve(&parts(peer, &headers), ip, proxies).0
+        };
+
+        // the first address may be made up by the client, the proxies saw 192.0.2.9
+        assert_eq!(client(PROXY, &proxies).to_string(), "192.0.2.9");
+        assert_eq!(client(CLIENT, &proxies).to_string(), "203.0.113.7");
+        assert_eq!(client(PROXY, &[]).to_string(), "10.0.0.1");
+
+        let peer = PROXY.parse::<SocketAddr>().unwrap().ip();
+        assert_eq!(
+            ClientIp::resolve(&parts(PROXY, &[]), peer, &proxies).0,
+            peer
+        );
+    }
+
+    #[test]
+    fn unusable_base_urls_should_be_refused() {
+        for value in ["", "sho.rt", "ftp://sho.rt", "mailto:admin@sho.rt"] {
+            assert!(parse_base_url(value).is_err(), "{}", value);
+        }
+        assert!(parse_base_url("http://localhost:8080").is_ok());
+    }
+}
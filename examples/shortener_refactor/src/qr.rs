@@ -0,0 +1,91 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, Luma};
+use qrcode::{render::svg, EcLevel, QrCode};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::ShortenError;
+
+const DEFAULT_SIZE: u32 = 256;
+const MAX_SIZE: u32 = 2048;
+
+/// Query of `GET /:id/qr`, the format falls back to the `Accept` header and then png.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QrQuery {
+    /// `png` or `svg`.
+    format: Option<String>,
+    /// Minimum width and height in pixels, the code is never scaled down below that.
+    /// Sizes above 2048 are treated as 2048.
+    size: Option<u32>,
+    /// Error correction level: L, M (the default), Q or H.
+    ec: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrFormat {
+    Png,
+    Svg,
+}
+
+impl QrFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            QrFormat::Png => "image/png",
+            QrFormat::Svg => "image/svg+xml",
+        }
+    }
+}
+
+impl QrQuery {
+    pub fn format(&self, accept: Option<&str>) -> Result<QrFormat, ShortenError> {
+        match self.format.as_deref() {
+            Some("png") => Ok(QrFormat::Png),
+            Some("svg") => Ok(QrFormat::Svg),
+            Some(other) => Err(ShortenError::InvalidQrOption(format!(
+                "format {} is not one of png, svg",
+                other
+            ))),
+            None if accept.is_some_and(|accept| accept.contains("image/svg+xml")) => {
+                Ok(QrFormat::Svg)
+            }
+            None => Ok(QrFormat::Png),
+        }
+    }
+
+    /// Encode `data` as a qr code image.
+    pub fn render(&self, data: &str, format: QrFormat) -> Result<Vec<u8>, ShortenError> {
+        let size = self.size.unwrap_or(DEFAULT_SIZE).min(MAX_SIZE);
+        let ec = match self.ec.as_deref().map(str::to_ascii_uppercase).as_deref() {
+            Some("L") => EcLevel::L,
+            None | Some("M") => EcLevel::M,
+            Some("Q") => EcLevel::Q,
+            Some("H") => EcLevel::H,
+            Some(other) => {
+                return Err(ShortenError::InvalidQrOption(format!(
+                    "ec {} is not one of L, M, Q, H",
+                    other
+                )))
+            }
+        };
+        let code = QrCode::with_error_correction_level(data, ec)
+            .map_err(|e| ShortenError::InvalidQrOption(e.to_string()))?;
+        match format {
+            QrFormat::Png => {
+                let image = code.render::<Luma<u8>>().min_dimensions(size, size).build();
+                let mut png = Cursor::new(Vec::new());
+                // writing into memory only fails for dimensions png cannot hold
+                image
+                    .write_to(&mut png, ImageFormat::Png)
+                    .expect("qr code fits in a png");
+                Ok(png.into_inner())
+            }
+            QrFormat::Svg => Ok(code
+                .render::<svg::Color>()
+                .min_dimensions(size, size)
+                .build()
+                .into_bytes()),
+        }
+    }
+}
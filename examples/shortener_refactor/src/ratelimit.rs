@@ -0,0 +1,158 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use dashmap::DashMap;
+use tokio::task::JoinHandle;
+
+use crate::{AppState, Authenticated, ClientIp, ShortenError, API_KEY_HEADER};
+
+/// Token bucket rate limiter, one bucket per client. A bucket holds up to a minute
+/// worth of requests and refills continuously.
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: DashMap<String, Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn per_minute(requests: u32) -> Self {
+        let capacity = f64::from(requests.max(1));
+        Self {
+            capacity,
+            refill_per_sec: capacity / 60.0,
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Take a token from the bucket of `client`, or tell how long until one is available.
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        self.check_n(client, 1)
+    }
+
+    /// Take `tokens` tokens at once. More than a full bucket is granted once the bucket
+    /// is full and leaves it in debt, so the client waits for all of them to refill.
+    pub fn check_n(&self, client: &str, tokens: u32) -> Result<(), Duration> {
+        self.withdraw(client, f64::from(tokens))
+    }
+
+    /// Like `check`, but leaves the token in the bucket.
+    pub fn peek(&self, client: &str) -> Result<(), Duration> {
+        self.withdraw(client, 0.0)
+    }
+
+    fn withdraw(&self, client: &str, tokens: f64) -> Result<(), Duration> {
+        let needed = tokens.clamp(1.0, self.capacity);
+        let now = Instant::now();
+        let mut bucket = self.buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated = now;
+        if bucket.tokens >= needed {
+            bucket.tokens -= tokens;
+            Ok(())
+        } else {
+            let wait = (needed - bucket.tokens) / self.refill_per_sec;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+
+    /// Periodically forget clients whose bucket has refilled completely.
+    pub fn spawn_cleanup(self: &Arc<Self>, period: Duration) -> JoinHandle<()> {
+        let limiter = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let full_after = Duration::from_secs_f64(limiter.capacity / limiter.refill_per_sec);
+                limiter
+                    .buckets
+                    .retain(|_, bucket| bucket.updated.elapsed() < full_after);
+            }
+        })
+    }
+}
+
+/// Middleware rejecting clients that ran out of budget with 429 and `Retry-After`.
+/// Clients are told apart by ip, see `ClientIp`.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    ClientIp(ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Result<Response, ShortenError> {
+    take(&limiter, &format!("ip:{}", ip), 1)?;
+    Ok(next.run(req).await)
+}
+
+/// Like `rate_limit`, but a client with a valid api key gets the bucket of that key
+/// instead of the one of its ip. Unknown keys count against the ip, and keys are only
+/// looked up while the ip has budget left, so made up keys cannot flood the storage.
+pub async fn rate_limit_by_key(
+    State((limiter, state)): State<(Arc<RateLimiter>, AppState)>,
+    ClientIp(ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Result<Response, ShortenError> {
+    let (mut parts, body) = req.into_parts();
+    let client = if parts.headers.contains_key(API_KEY_HEADER) {
+        limiter.peek(&format!("ip:{}", ip)).map_err(retry_after)?;
+        match Authenticated::from_request_parts(&mut parts, &state).await {
+            Ok(auth) => {
+                let client = format!("key:{}", auth.0.id);
+                // saves the handler a second lookup
+                parts.extensions.insert(auth);
+                client
+            }
+            Err(_) => format!("ip:{}", ip),
+        }
+    } else {
+        format!("ip:{}", ip)
+    };
+    take(&limiter, &client, 1)?;
+    // handlers doing more than one thing per request charge the rest
+    parts.extensions.insert(Budget { limiter, client });
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+/// The bucket a request was charged to by `rate_limit_by_key`.
+#[derive(Debug, Clone)]
+pub(crate) struct Budget {
+    limiter: Arc<RateLimiter>,
+    client: String,
+}
+
+impl Budget {
+    /// Take `tokens` more tokens, on top of the one the request already took.
+    pub(crate) fn charge(&self, tokens: u32) -> Result<(), ShortenError> {
+        match tokens {
+            0 => Ok(()),
+            tokens => take(&self.limiter, &self.client, tokens),
+        }
+    }
+}
+
+fn take(limiter: &RateLimiter, client: &str, tokens: u32) -> Result<(), ShortenError> {
+    limiter.check_n(client, tokens).map_err(retry_after)
+}
+
+// rounded up, a client retrying right on time must find a token
+fn retry_after(wait: Duration) -> ShortenError {
+    ShortenError::RateLimited((wait.as_secs_f64().ceil() as u64).max(1))
+}
@@ -1,75 +1,937 @@
-use anyhow::Result;
+use std::{
+    future::IntoFuture,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{get, post, MethodRouter},
     Router,
 };
-use axum_macros::FromRequest;
-use tokio::net::TcpListener;
-use tracing::info;
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use axum_macros::{FromRequest, FromRequestParts};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::{net::TcpListener, sync::Notify};
+use tracing::{info, warn};
 
 use axum::{
-    extract::{Path, State},
-    response::IntoResponse,
-    Json,
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Path, Query, State},
+    response::{Html, IntoResponse, Response},
+    Extension, Form, Json,
 };
 
-use http::{header::LOCATION, HeaderMap, StatusCode, Uri};
+use chrono::{DateTime, TimeDelta, Utc};
+use http::{
+    header::{
+        ACCEPT, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, LOCATION, REFERER, USER_AGENT,
+    },
+    HeaderMap, HeaderValue, StatusCode, Uri,
+};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
-use crate::{AppState, ShortenError, LISTEN_ADDR};
+use crate::{
+    auth::{Admin, Authenticated},
+    error::negotiate_errors,
+    id::validate_id_length,
+    metrics::track_metrics,
+    openapi::{DOCS_PAGE, SWAGGER_UI_CSS, SWAGGER_UI_JS},
+    preview,
+    public_url::parse_base_url,
+    ratelimit::{rate_limit, rate_limit_by_key, Budget},
+    validate::validate_redirect_status,
+    ApiDoc, ApiKey, AppState, BaseUrl, Click, ClientIp, ExportFormat, LinkOptions, QrQuery,
+    RateLimiter, ShortenError, ShortenUrl, Shortened, CREATE_RATE_PER_MIN, DEFAULT_REDIRECT_STATUS,
+    ID_LENGTH, IMPORT_MAX_BYTES, LISTEN_ADDR, MAX_BATCH_SIZE, PURGE_INTERVAL_SECS,
+    REDIRECT_RATE_PER_MIN, SHUTDOWN_DELAY_SECS, SHUTDOWN_DRAIN_SECS,
+};
 
 pub async fn run() -> Result<()> {
+    // links would quietly be built from the Host header instead
+    if let Some(Err(e)) = dotenvy::var("PUBLIC_BASE_URL")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(|v| parse_base_url(&v))
+    {
+        bail!("invalid PUBLIC_BASE_URL {}", e);
+    }
+    // shorter ids would not pass as aliases when imported again
+    if let Err(e) = validate_id_length(*ID_LENGTH) {
+        bail!("invalid ID_LENGTH {}", e);
+    }
     // init app state
     let state = AppState::try_new().await?;
+    let purge = state.spawn_expiry_purge(Duration::from_secs(*PURGE_INTERVAL_SECS));
+    #[cfg(unix)]
+    let reload = spawn_blocklist_reload(state.clone());
 
     // bind listener
     let listener = TcpListener::bind(&*LISTEN_ADDR).await?;
     info!("Listening on: {}", &*LISTEN_ADDR);
     // init app router
-    let app = Router::new()
-        .route("/", post(shortener_handler))
-        .route("/:id", get(redirect_handler))
-        .fallback(not_found)
-        .with_state(state);
+    let app = app(state.clone());
+
+    // init server, on a signal fail readiness first so load balancers stop sending new
+    // connections, then stop accepting and give in-flight requests some time
+    let stopping = Arc::new(Notify::new());
+    let signal = {
+        let (state, stopping) = (state.clone(), stopping.clone());
+        async move {
+            shutdown_signal().await;
+            state.start_draining();
+            info!(
+                "Shutting down, still accepting for {}s",
+                *SHUTDOWN_DELAY_SECS
+            );
+            tokio::time::sleep(Duration::from_secs(*SHUTDOWN_DELAY_SECS)).await;
+            info!(
+                "Stopped accepting, draining for up to {}s",
+                *SHUTDOWN_DRAIN_SECS
+            );
+            stopping.notify_one();
+        }
+    };
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(signal);
+    tokio::select! {
+        ret = server.into_future() => ret?,
+        _ = async {
+            stopping.notified().await;
+            tokio::time::sleep(Duration::from_secs(*SHUTDOWN_DRAIN_SECS)).await;
+        } => warn!("Drain timeout reached, dropping the remaining connections"),
+    }
 
-    // init server
-    axum::serve(listener, app.into_make_service()).await?;
+    purge.abort();
+    #[cfg(unix)]
+    reload.abort();
+    state.close().await;
+    info!("Shut down");
     Ok(())
 }
 
-#[derive(Debug, Deserialize)]
+/// Every route of the service, `run` serves it and tests can drive it directly.
+pub fn app(state: AppState) -> Router {
+    // creating links and following them get separate budgets
+    let limits = Limits {
+        create: Arc::new(RateLimiter::per_minute(*CREATE_RATE_PER_MIN)),
+        redirect: Arc::new(RateLimiter::per_minute(*REDIRECT_RATE_PER_MIN)),
+        state: state.clone(),
+    };
+    limits.create.spawn_cleanup(Duration::from_secs(60));
+    limits.redirect.spawn_cleanup(Duration::from_secs(60));
+
+    ROUTES
+        .iter()
+        .fold(Router::new(), |router, (path, route)| {
+            router.route(path, route(&limits))
+        })
+        .fallback(not_found)
+        .layer(middleware::from_fn_with_state(state.clone(), track_metrics))
+        .layer(middleware::from_fn(negotiate_errors))
+        .with_state(state)
+}
+
+/// Paths of every route `app` serves, in axum syntax like `/:id/qr`.
+pub fn routes() -> impl Iterator<Item = &'static str> {
+    ROUTES.iter().map(|(path, _)| *path)
+}
+
+// a path and how to build its handlers
+type Route = (&'static str, fn(&Limits) -> MethodRouter<AppState>);
+
+// the only place routes are added, so `routes` cannot miss one
+const ROUTES: &[Route] = &[
+    ("/", |limits| limits.create(post(shortener_handler))),
+    ("/batch", |limits| limits.create(post(batch_handler))),
+    ("/healthz", |_| get(health_handler)),
+    ("/readyz", |_| get(ready_handler)),
+    ("/metrics", |_| get(metrics_handler)),
+    ("/openapi.json", |_| get(openapi_handler)),
+    ("/docs", |_| get(docs_handler)),
+    ("/docs/swagger-ui-bundle.js", |_| get(docs_js_handler)),
+    ("/docs/swagger-ui.css", |_| get(docs_css_handler)),
+    ("/admin/blocklist/reload", |_| {
+        post(reload_blocklist_handler)
+    }),
+    ("/admin/export", |_| get(export_handler)),
+    ("/admin/import", |_| {
+        post(import_handler).layer(DefaultBodyLimit::max(*IMPORT_MAX_BYTES))
+    }),
+    ("/admin/links/:id/disable", |_| post(disable_handler)),
+    ("/admin/links/:id/enable", |_| post(enable_handler)),
+    ("/:id", |limits| {
+        // the layer only wraps the methods added before it
+        limits
+            .redirect(get(redirect_handler))
+            .patch(retarget_handler)
+            .delete(delete_handler)
+    }),
+    ("/:id/unlock", |limits| {
+        limits.redirect(post(unlock_handler))
+    }),
+    ("/:id/info", |_| get(info_handler)),
+    ("/:id/stats", |_| get(stats_handler)),
+    ("/:id/qr", |_| get(qr_handler)),
+];
+
+// rate limiters shared by the routes of one `app`
+struct Limits {
+    create: Arc<RateLimiter>,
+    redirect: Arc<RateLimiter>,
+    state: AppState,
+}
+
+impl Limits {
+    fn create(&self, route: MethodRouter<AppState>) -> MethodRouter<AppState> {
+        let limiter = (self.create.clone(), self.state.clone());
+        route.layer(middleware::from_fn_with_state(limiter, rate_limit_by_key))
+    }
+
+    fn redirect(&self, route: MethodRouter<AppState>) -> MethodRouter<AppState> {
+        route.layer(middleware::from_fn_with_state(
+            self.redirect.clone(),
+            rate_limit,
+        ))
+    }
+}
+
+/// Re-read the blocklist on every SIGHUP.
+#[cfg(unix)]
+fn spawn_blocklist_reload(state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                warn!("listen for SIGHUP failed: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            if let Err(e) = state.reload_blocklist() {
+                warn!("reload blocklist failed, keeping the current one: {}", e);
+            }
+        }
+    })
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("listen for ctrl-c failed: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!("listen for SIGTERM failed: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ShortenRequest {
     url: String,
+    alias: Option<String>,
+    expires_at: Option<DateTime<Utc>>,
+    ttl_seconds: Option<u64>,
+    redirect_status: Option<u16>,
+    /// Visitors have to enter it before being redirected.
+    password: Option<String>,
 }
-#[derive(Debug, Serialize)]
+
+impl ShortenRequest {
+    fn expiry(&self) -> Result<Option<DateTime<Utc>>, ShortenError> {
+        let expires_at = match (self.expires_at, self.ttl_seconds) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => {
+                return Err(ShortenError::InvalidExpiry(
+                    "expires_at and ttl_seconds are mutually exclusive".to_string(),
+                ))
+            }
+            (Some(expires_at), None) => expires_at,
+            (None, Some(ttl)) => i64::try_from(ttl)
+                .ok()
+                .and_then(TimeDelta::try_seconds)
+                .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+                .ok_or_else(|| {
+                    ShortenError::InvalidExpiry(format!("ttl_seconds {} is too large", ttl))
+                })?,
+        };
+        if expires_at <= Utc::now() {
+            return Err(ShortenError::InvalidExpiry(format!(
+                "{} is in the past",
+                expires_at
+            )));
+        }
+        Ok(Some(expires_at))
+    }
+
+    fn into_parts(self, api_key: &ApiKey) -> Result<(String, LinkOptions), ShortenError> {
+        if let Some(status) = self.redirect_status {
+            validate_redirect_status(status)?;
+        }
+        let opts = LinkOptions {
+            expires_at: self.expiry()?,
+            alias: self.alias,
+            api_key_id: Some(api_key.id),
+            redirect_status: self.redirect_status,
+            password: self.password,
+        };
+        Ok((self.url, opts))
+    }
+}
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ShortenResponse {
     url: String,
+    /// Secret needed to retarget or delete the link, only returned to its creator.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RetargetRequest {
+    url: String,
+}
+
+/// Outcome of one url of a batch, in the position it was sent.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum BatchResult {
+    Created {
+        id: String,
+        url: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    Failed {
+        status: u16,
+        code: &'static str,
+        error: String,
+    },
+}
+
+/// Body of `POST /:id/unlock`, as sent by the password form.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UnlockForm {
+    password: String,
+}
+
+/// Body of `POST /admin/links/:id/disable`.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct DisableRequest {
+    /// 451 (the default) or 410.
+    status: Option<u16>,
+    /// Kept for the admins, never shown to visitors.
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BlocklistReloaded {
+    /// Hosts, wildcards and networks now in effect.
+    entries: usize,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// `jsonl` (the default) or `csv`.
+    format: Option<ExportFormat>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    /// `jsonl` (the default) or `csv`.
+    format: Option<ExportFormat>,
+    /// Only report what would be imported.
+    #[serde(default)]
+    dry_run: bool,
 }
 
 #[derive(FromRequest)]
 #[from_request(via(axum::Json), rejection(ShortenError))]
 pub struct ShortenJson<T>(T);
 
+#[derive(FromRequestParts)]
+#[from_request(via(Query), rejection(ShortenError))]
+pub struct ShortenQuery<T>(T);
+
+#[derive(FromRequest)]
+#[from_request(via(Form), rejection(ShortenError))]
+pub struct ShortenForm<T>(T);
+
+/// Shorten a url, reusing the existing link when the same url was shortened without options.
+#[utoipa::path(
+    post,
+    path = "/",
+    request_body = ShortenRequest,
+    responses(
+        (status = 201, description = "Link created", body = ShortenResponse),
+        (status = 400, description = "`invalid_json`, `invalid_url`, `invalid_alias`, `invalid_expiry` or `invalid_redirect_status`", body = JsonError),
+        (status = 401, description = "`unauthorized`: missing or revoked api key", body = JsonError),
+        (status = 409, description = "`alias_taken`", body = JsonError),
+        (status = 429, description = "`rate_limited`, see `Retry-After`", body = JsonError),
+        (status = 500, description = "`internal_error`", body = JsonError),
+        (status = 503, description = "`id_space_exhausted`", body = JsonError),
+    ),
+    security(("api_key" = []))
+)]
 async fn shortener_handler(
     State(state): State<AppState>,
-    ShortenJson(ShortenRequest { url }): ShortenJson<ShortenRequest>,
+    Authenticated(api_key): Authenticated,
+    base_url: BaseUrl,
+    ShortenJson(req): ShortenJson<ShortenRequest>,
 ) -> Result<impl IntoResponse, ShortenError> {
-    let id = state.shorten(&url).await?;
+    let (url, opts) = req.into_parts(&api_key)?;
+    let shortened = state.shorten(&url, opts).await?;
     let body = Json(ShortenResponse {
-        url: format!("http://{}/{}", &*LISTEN_ADDR, id),
+        url: base_url.link(&shortened.id),
+        token: shortened.token,
     });
     Ok((StatusCode::CREATED, body))
 }
 
+/// Shorten many urls at once, each one succeeds or fails on its own.
+#[utoipa::path(
+    post,
+    path = "/batch",
+    request_body = Vec<ShortenRequest>,
+    responses(
+        (status = 200, description = "One result per url, in request order", body = Vec<BatchResult>),
+        (status = 400, description = "`invalid_json` or `batch_too_large`", body = JsonError),
+        (status = 401, description = "`unauthorized`: missing or revoked api key", body = JsonError),
+        (status = 429, description = "`rate_limited`, every url counts as a request, see `Retry-After`", body = JsonError),
+        (status = 500, description = "`internal_error`", body = JsonError),
+        (status = 503, description = "`id_space_exhausted`", body = JsonError),
+    ),
+    security(("api_key" = []))
+)]
+async fn batch_handler(
+    State(state): State<AppState>,
+    Extension(budget): Extension<Budget>,
+    Authenticated(api_key): Authenticated,
+    base_url: BaseUrl,
+    ShortenJson(reqs): ShortenJson<Vec<ShortenRequest>>,
+) -> Result<impl IntoResponse, ShortenError> {
+    if reqs.len() > *MAX_BATCH_SIZE {
+        return Err(ShortenError::BatchTooLarge(*MAX_BATCH_SIZE));
+    }
+    // every url counts against the create budget like a request of its own
+    budget.charge(reqs.len().saturating_sub(1) as u32)?;
+    // requests with an invalid expiry never reach the state, remember where they were
+    let mut results: Vec<Option<Result<Shortened, ShortenError>>> = Vec::with_capacity(reqs.len());
+    let mut valid = Vec::new();
+    for req in reqs {
+        match req.into_parts(&api_key) {
+            Ok(parts) => {
+                results.push(None);
+                valid.push(parts);
+            }
+            Err(e) => results.push(Some(Err(e))),
+        }
+    }
+    let mut shortened = state.shorten_batch(valid).await?.into_iter();
+    let body: Vec<BatchResult> = results
+        .into_iter()
+        .map(|result| match result.or_else(|| shortened.next()) {
+            Some(Ok(Shortened { id, token })) => BatchResult::Created {
+                url: base_url.link(&id),
+                id,
+                token,
+            },
+            Some(Err(e)) => BatchResult::Failed {
+                status: e.status().as_u16(),
+                code: e.code(),
+                error: e.message(),
+            },
+            None => unreachable!("shorten_batch returns one result per url"),
+        })
+        .collect();
+    Ok(Json(body))
+}
+
+/// Follow a link. An id ending in `+` shows a preview page instead of redirecting,
+/// password protected links show a form posting to `/{id}/unlock`.
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    params(("id" = String, Path, description = "Short id or alias")),
+    responses(
+        (status = 301, description = "Permanent redirect to the target url"),
+        (status = 302, description = "Temporary redirect to the target url"),
+        (status = 307, description = "Temporary redirect keeping the method"),
+        (status = 308, description = "Permanent redirect keeping the method"),
+        (status = 200, description = "Preview page for ids ending in `+`, password form for protected links", body = String, content_type = "text/html"),
+        (status = 401, description = "`password_required`: protected links have no preview", body = JsonError),
+        (status = 404, description = "`not_found`", body = JsonError),
+        (status = 410, description = "`expired` or `disabled`", body = JsonError),
+        (status = 451, description = "`disabled`", body = JsonError),
+        (status = 429, description = "`rate_limited`, see `Retry-After`", body = JsonError),
+        (status = 500, description = "`internal_error`, also for a stored url that is not usable", body = JsonError),
+    )
+)]
 async fn redirect_handler(
     Path(id): Path<String>,
     State(state): State<AppState>,
-) -> Result<impl IntoResponse, ShortenError> {
-    let url = state.get_url(&id).await?;
+    ClientIp(ip): ClientIp,
+    base_url: BaseUrl,
+    req_headers: HeaderMap,
+) -> Result<Response, ShortenError> {
+    // `/abc123+` shows where the link goes instead of going there
+    if let Some(id) = id.strip_suffix('+') {
+        let info = state.info(id).await?;
+        return Ok(Html(preview::render(&info)).into_response());
+    }
+    let ShortenUrl {
+        url,
+        redirect_status,
+        password_hash,
+        ..
+    } = match state.lookup(&id).await {
+        Ok(row) => row,
+        Err(e) => {
+            match e {
+                ShortenError::Notfound(_) => state.metrics().record_redirect("miss"),
+                ShortenError::Expired(_) => state.metrics().record_redirect("expired"),
+                ShortenError::Disabled(..) => state.metrics().record_redirect("disabled"),
+                _ => {}
+            }
+            return Err(e);
+        }
+    };
+    if password_hash.is_some() {
+        state.metrics().record_redirect("protected");
+        let headers = [(CACHE_CONTROL, "no-store")];
+        let page = preview::password_form(&id, &base_url.link(&id), None);
+        return Ok((headers, Html(page)).into_response());
+    }
+    state.metrics().record_redirect("hit");
+    let location = location(&id, &url)?;
     info!("Redirected to: {}", url);
+    spawn_click(state, id, ip, &req_headers);
+
+    let status = redirect_status
+        .and_then(|status| StatusCode::from_u16(status as u16).ok())
+        .unwrap_or_else(|| StatusCode::from_u16(*DEFAULT_REDIRECT_STATUS).unwrap());
     let mut headers = HeaderMap::new();
-    headers.insert(LOCATION, url.parse().unwrap());
-    Ok((StatusCode::PERMANENT_REDIRECT, headers, ""))
+    headers.insert(LOCATION, location);
+    // keep browsers and proxies from remembering redirects that may change
+    if matches!(status, StatusCode::FOUND | StatusCode::TEMPORARY_REDIRECT) {
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    }
+    Ok((status, headers, "").into_response())
+}
+
+/// Check the password of a protected link and redirect to its target.
+#[utoipa::path(
+    post,
+    path = "/{id}/unlock",
+    params(("id" = String, Path, description = "Short id or alias")),
+    request_body(content = UnlockForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "Redirect to the target url"),
+        (status = 401, description = "`wrong_password`, html clients get the form again", body = JsonError),
+        (status = 404, description = "`not_found`", body = JsonError),
+        (status = 410, description = "`expired` or `disabled`", body = JsonError),
+        (status = 415, description = "`invalid_input`: the body is not a form", body = JsonError),
+        (status = 422, description = "`invalid_input`: the form has no password", body = JsonError),
+        (status = 429, description = "`locked` after too many wrong passwords or `rate_limited`, see `Retry-After`", body = JsonError),
+        (status = 451, description = "`disabled`", body = JsonError),
+        (status = 500, description = "`internal_error`", body = JsonError),
+    )
+)]
+async fn unlock_handler(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    base_url: BaseUrl,
+    req_headers: HeaderMap,
+    ShortenForm(UnlockForm { password }): ShortenForm<UnlockForm>,
+) -> Result<Response, ShortenError> {
+    let url = match state.unlock(&id, &password).await {
+        Ok(row) => row.url,
+        Err(e @ (ShortenError::WrongPassword(_) | ShortenError::Locked(_))) => {
+            // the form again with the reason, json clients still get the error body
+            let page = preview::password_form(&id, &base_url.link(&id), Some(&e.message()));
+            let (parts, _) = e.into_response().into_parts();
+            return Ok(Response::from_parts(parts, Body::from(page)));
+        }
+        Err(e) => return Err(e),
+    };
+    state.metrics().record_redirect("hit");
+    let location = location(&id, &url)?;
+    info!("Unlocked and redirected to: {}", url);
+    spawn_click(state, id, ip, &req_headers);
+    // 303 whatever the link's own status, a 307 or 308 would post the password to the target
+    let headers = [
+        (LOCATION, location),
+        (CACHE_CONTROL, HeaderValue::from_static("no-store")),
+    ];
+    Ok((StatusCode::SEE_OTHER, headers).into_response())
+}
+
+// urls are validated when shortened, but rows stored before that may still be unusable,
+// which is our fault and not the visitor's
+fn location(id: &str, url: &str) -> Result<HeaderValue, ShortenError> {
+    HeaderValue::from_str(url)
+        .map_err(|_| ShortenError::UnusableStoredUrl(id.to_string(), url.to_string()))
+}
+
+// record the click in the background, the redirect should not wait for it
+fn spawn_click(state: AppState, id: String, ip: IpAddr, req_headers: &HeaderMap) {
+    let header = |name| {
+        req_headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    let click = Click {
+        referrer: header(REFERER),
+        user_agent: header(USER_AGENT),
+        client_ip: Some(ip),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = state.record_click(&id, click).await {
+            warn!("record click for {} failed: {}", id, e);
+        }
+    });
+}
+
+/// Point a link somewhere else.
+#[utoipa::path(
+    patch,
+    path = "/{id}",
+    params(("id" = String, Path, description = "Short id or alias")),
+    request_body = RetargetRequest,
+    responses(
+        (status = 204, description = "Link retargeted"),
+        (status = 400, description = "`invalid_json` or `invalid_url`", body = JsonError),
+        (status = 403, description = "`forbidden`: missing or wrong owner token", body = JsonError),
+        (status = 404, description = "`not_found`", body = JsonError),
+        (status = 409, description = "`shared_link`: others were handed the same link", body = JsonError),
+        (status = 500, description = "`internal_error`", body = JsonError),
+    ),
+    security(("owner_token" = []))
+)]
+async fn retarget_handler(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    ShortenJson(RetargetRequest { url }): ShortenJson<RetargetRequest>,
+) -> Result<impl IntoResponse, ShortenError> {
+    let TypedHeader(Authorization(bearer)) = bearer.ok_or(ShortenError::Forbidden(id.clone()))?;
+    state.retarget(&id, bearer.token(), &url).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete a link.
+#[utoipa::path(
+    delete,
+    path = "/{id}",
+    params(("id" = String, Path, description = "Short id or alias")),
+    responses(
+        (status = 204, description = "Link deleted"),
+        (status = 403, description = "`forbidden`: missing or wrong owner token", body = JsonError),
+        (status = 404, description = "`not_found`", body = JsonError),
+        (status = 409, description = "`shared_link`: others were handed the same link", body = JsonError),
+        (status = 500, description = "`internal_error`", body = JsonError),
+    ),
+    security(("owner_token" = []))
+)]
+async fn delete_handler(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<impl IntoResponse, ShortenError> {
+    let TypedHeader(Authorization(bearer)) = bearer.ok_or(ShortenError::Forbidden(id.clone()))?;
+    state.delete(&id, bearer.token()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Liveness probe, answers as long as the process serves requests.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses((status = 200, description = "Alive", body = Object, example = json!({"status": "ok"})))
+)]
+async fn health_handler() -> impl IntoResponse {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Readiness probe, fails while draining or when the database is unreachable.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "Ready for traffic", body = Readiness),
+        (status = 503, description = "Not ready", body = Readiness),
+    )
+)]
+async fn ready_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let readiness = state.readiness().await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
+
+/// Prometheus metrics.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses((status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"))
+)]
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.render_metrics(),
+    )
+}
+
+/// Where a link goes, without following it.
+#[utoipa::path(
+    get,
+    path = "/{id}/info",
+    params(("id" = String, Path, description = "Short id or alias")),
+    responses(
+        (status = 200, description = "Link details", body = LinkInfo),
+        (status = 401, description = "`password_required`", body = JsonError),
+        (status = 404, description = "`not_found`", body = JsonError),
+        (status = 410, description = "`expired` or `disabled`", body = JsonError),
+        (status = 451, description = "`disabled`", body = JsonError),
+        (status = 500, description = "`internal_error`", body = JsonError),
+    )
+)]
+async fn info_handler(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ShortenError> {
+    Ok(Json(state.info(&id).await?))
+}
+
+/// Clicks of a link per day.
+#[utoipa::path(
+    get,
+    path = "/{id}/stats",
+    params(("id" = String, Path, description = "Short id or alias")),
+    responses(
+        (status = 200, description = "Click counts", body = LinkStats),
+        (status = 404, description = "`not_found`", body = JsonError),
+        (status = 500, description = "`internal_error`", body = JsonError),
+    )
+)]
+async fn stats_handler(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ShortenError> {
+    Ok(Json(state.stats(&id).await?))
+}
+
+/// QR code of the short url.
+#[utoipa::path(
+    get,
+    path = "/{id}/qr",
+    params(("id" = String, Path, description = "Short id or alias"), QrQuery),
+    responses(
+        (status = 200, description = "The code as png or svg", content_type = ["image/png", "image/svg+xml"]),
+        (status = 400, description = "`invalid_input` or `invalid_qr_option`", body = JsonError),
+        (status = 404, description = "`not_found`", body = JsonError),
+        (status = 410, description = "`expired` or `disabled`", body = JsonError),
+        (status = 451, description = "`disabled`", body = JsonError),
+        (status = 500, description = "`internal_error`", body = JsonError),
+    )
+)]
+async fn qr_handler(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    base_url: BaseUrl,
+    ShortenQuery(query): ShortenQuery<QrQuery>,
+    req_headers: HeaderMap,
+) -> Result<impl IntoResponse, ShortenError> {
+    // unknown and expired links get no code
+    state.get_url(&id).await?;
+    let accept = req_headers.get(ACCEPT).and_then(|v| v.to_str().ok());
+    let format = query.format(accept)?;
+    let image = query.render(&base_url.link(&id), format)?;
+    Ok(([(CONTENT_TYPE, format.content_type())], image))
+}
+
+/// Take a link down, it answers with 451 or 410 instead of redirecting.
+#[utoipa::path(
+    post,
+    path = "/admin/links/{id}/disable",
+    params(("id" = String, Path, description = "Short id or alias")),
+    request_body = DisableRequest,
+    responses(
+        (status = 204, description = "Link disabled"),
+        (status = 400, description = "`invalid_json` or `invalid_disable_status`", body = JsonError),
+        (status = 401, description = "`admin_required`", body = JsonError),
+        (status = 404, description = "`not_found`", body = JsonError),
+        (status = 500, description = "`internal_error`", body = JsonError),
+    ),
+    security(("admin_token" = []))
+)]
+async fn disable_handler(
+    _: Admin,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    ShortenJson(req): ShortenJson<DisableRequest>,
+) -> Result<impl IntoResponse, ShortenError> {
+    let status = req
+        .status
+        .unwrap_or(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS.as_u16());
+    state.disable(&id, status, req.reason.as_deref()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Let a disabled link redirect again.
+#[utoipa::path(
+    post,
+    path = "/admin/links/{id}/enable",
+    params(("id" = String, Path, description = "Short id or alias")),
+    responses(
+        (status = 204, description = "Link enabled"),
+        (status = 401, description = "`admin_required`", body = JsonError),
+        (status = 404, description = "`not_found`", body = JsonError),
+        (status = 500, description = "`internal_error`", body = JsonError),
+    ),
+    security(("admin_token" = []))
+)]
+async fn enable_handler(
+    _: Admin,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ShortenError> {
+    state.enable(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Re-read `BLOCKLIST_FILE`, the current list stays in effect when it cannot be loaded.
+#[utoipa::path(
+    post,
+    path = "/admin/blocklist/reload",
+    responses(
+        (status = 200, description = "Blocklist reloaded", body = BlocklistReloaded),
+        (status = 401, description = "`admin_required`", body = JsonError),
+        (status = 500, description = "`invalid_blocklist`", body = JsonError),
+    ),
+    security(("admin_token" = []))
+)]
+async fn reload_blocklist_handler(
+    _: Admin,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ShortenError> {
+    let entries = state.reload_blocklist()?;
+    Ok(Json(BlocklistReloaded { entries }))
+}
+
+/// Every link with all its columns, streamed page by page.
+#[utoipa::path(
+    get,
+    path = "/admin/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "The links as json lines or csv", content_type = ["application/x-ndjson", "text/csv"]),
+        (status = 400, description = "`invalid_input`: unknown `format`", body = JsonError),
+        (status = 401, description = "`admin_required`", body = JsonError),
+    ),
+    security(("admin_token" = []))
+)]
+async fn export_handler(
+    _: Admin,
+    State(state): State<AppState>,
+    ShortenQuery(query): ShortenQuery<ExportQuery>,
+) -> impl IntoResponse {
+    let format = query.format.unwrap_or_default();
+    let disposition = format!("attachment; filename=\"links.{}\"", format.extension());
+    (
+        [
+            (CONTENT_TYPE, format.content_type().to_string()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        // a failing page ends the download early, the error is in the log
+        Body::from_stream(state.export(format)),
+    )
+}
+
+/// Store the links of an export under their own ids. Rows that fail are reported, the
+/// rest is stored in one transaction.
+#[utoipa::path(
+    post,
+    path = "/admin/import",
+    params(ImportQuery),
+    request_body(content = String, description = "Links as written by the export", content_type = "application/x-ndjson"),
+    responses(
+        (status = 200, description = "What was (or on a dry run would be) imported", body = ImportReport),
+        (status = 400, description = "`invalid_input`: unknown `format` or `dry_run`", body = JsonError),
+        (status = 401, description = "`admin_required`", body = JsonError),
+        (status = 413, description = "Body larger than `IMPORT_MAX_BYTES`"),
+        (status = 500, description = "`internal_error`", body = JsonError),
+    ),
+    security(("admin_token" = []))
+)]
+async fn import_handler(
+    _: Admin,
+    State(state): State<AppState>,
+    ShortenQuery(query): ShortenQuery<ImportQuery>,
+    body: Bytes,
+) -> Result<impl IntoResponse, ShortenError> {
+    let format = query.format.unwrap_or_default();
+    Ok(Json(state.import(format, &body, query.dry_run).await?))
+}
+
+async fn openapi_handler() -> impl IntoResponse {
+    Json(ApiDoc::openapi())
+}
+
+async fn docs_handler() -> impl IntoResponse {
+    Html(DOCS_PAGE)
+}
+
+async fn docs_js_handler() -> impl IntoResponse {
+    docs_asset("text/javascript", SWAGGER_UI_JS)
+}
+
+async fn docs_css_handler() -> impl IntoResponse {
+    docs_asset("text/css", SWAGGER_UI_CSS)
+}
+
+// the assets only change with the binary
+fn docs_asset(content_type: &'static str, body: &'static str) -> impl IntoResponse {
+    (
+        [
+            (CONTENT_TYPE, content_type),
+            (CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        body,
+    )
 }
 
 pub async fn not_found(uri: Uri) -> impl IntoResponse {
@@ -1,69 +1,701 @@
-use serde::Deserialize;
-use sqlx::{postgres::PgPoolOptions, FromRow, PgPool};
+use std::{
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::{stream, Stream};
+use serde::Serialize;
+use sqlx::FromRow;
+use tokio::task::JoinHandle;
 use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    blocklist::Blocklist,
+    cache::UrlCache,
+    id::IdGenerator,
+    lockout::Lockout,
+    metrics::Metrics,
+    password,
+    storage::{self, ApiKey, Imported, LinkFilter, NewUrl, PoolStats, Storage},
+    token,
+    transfer::{self, ExportFormat, ImportFailure, ImportReport, EXPORT_PAGE},
+    validate::{normalize_url, validate_alias, validate_disable_status, validate_redirect_status},
+    ShortenError, ShortenUrl, BLOCKLIST_FILE, BLOCK_PRIVATE_TARGETS, SKIP_MIGRATIONS,
+    UNLOCK_LOCKOUT_SECS, UNLOCK_MAX_FAILURES, URL_CACHE_CAPACITY, URL_CACHE_NEGATIVE_TTL_SECS,
+    URL_CACHE_TTL_SECS,
+};
 
-use crate::ShortenError;
-const MAX_CONNECTION: u32 = 12;
 #[derive(Debug, Clone)]
 pub struct AppState {
-    db: PgPool,
+    storage: Arc<dyn Storage>,
+    cache: Option<Arc<UrlCache>>,
+    ids: Arc<IdGenerator>,
+    // set once shutdown starts, readiness fails from then on
+    draining: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
+    // swapped as a whole on reload, checks keep the list they started with
+    blocklist: Arc<RwLock<Arc<Blocklist>>>,
+    unlock_failures: Arc<Lockout>,
+}
+
+/// Per-link settings chosen by the creator of a short url.
+#[derive(Debug, Default, Clone)]
+pub struct LinkOptions {
+    pub alias: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The api key the link is created with, kept for attribution only.
+    pub api_key_id: Option<i64>,
+    /// One of 301, 302, 307 or 308, `None` follows `DEFAULT_REDIRECT_STATUS`.
+    pub redirect_status: Option<u16>,
+    /// Asked from visitors before redirecting, only its hash is stored.
+    pub password: Option<String>,
+}
+
+/// What we know about a single redirect.
+#[derive(Debug, Default, Clone)]
+pub struct Click {
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub client_ip: Option<IpAddr>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LinkStats {
+    pub id: String,
+    pub total_clicks: i64,
+    pub daily: Vec<DailyClicks>,
 }
 
-#[derive(FromRow, Debug, Deserialize)]
-struct ShortenUrl {
-    #[sqlx(default)]
-    id: String,
-    #[sqlx(default)]
-    url: String,
+/// Where a link points, for checking it before following it.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LinkInfo {
+    pub id: String,
+    pub url: String,
+    /// Unknown for rows written before creation times were recorded.
+    pub created_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub clicks: i64,
+}
+
+#[derive(FromRow, Debug, Serialize, ToSchema)]
+pub struct DailyClicks {
+    pub day: NaiveDate,
+    pub clicks: i64,
+}
+
+/// Body of the readiness probe.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub draining: bool,
+    /// `ok` or why the database could not be reached.
+    pub database: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<PoolStats>,
+}
+
+/// A stored link. Only whoever created the link gets its management `token`, a
+/// request that was deduplicated onto an existing link does not.
+#[derive(Debug, Clone)]
+pub struct Shortened {
+    pub id: String,
+    pub token: Option<String>,
+}
+
+impl Shortened {
+    fn new(new: &NewUrl, id: String, token: String) -> Self {
+        let token = (id == new.id).then_some(token);
+        Self { id, token }
+    }
+}
+
+impl LinkOptions {
+    /// Plain links are deduplicated by url, anything customised gets a row of its own.
+    pub(crate) fn is_shared(&self) -> bool {
+        self.alias.is_none()
+            && self.expires_at.is_none()
+            && self.redirect_status.is_none()
+            && self.password.is_none()
+    }
 }
 
 impl AppState {
+    /// Connect to the storage backend named by the `DATABASE_URL` scheme and apply
+    /// pending migrations unless `SKIP_MIGRATIONS` is set.
     pub async fn try_new() -> Result<Self, ShortenError> {
         let url = &dotenvy::var("DATABASE_URL")?;
-        let db = PgPoolOptions::new()
-            .max_connections(MAX_CONNECTION)
-            .connect(url)
-            .await?;
-        Ok(Self { db })
+        let storage = storage::connect(url).await?;
+        if *SKIP_MIGRATIONS {
+            info!("SKIP_MIGRATIONS is set, leaving the schema alone");
+        } else {
+            storage.migrate().await?;
+        }
+        let state = Self::new(storage);
+        state.reload_blocklist()?;
+        Ok(state)
     }
 
-    // id error message: Err(Database(PgDatabaseError { severity: Error, code: "23505", message: "重复键违反唯一约束\"shorten_urls_pkey\"", detail: Some("键值\"(id)=(1     )\" 已经存在"), hint: None, position: None, where: None, schema: Some("public"), table: Some("shorten_urls"), column: None, data_type: None, constraint: Some("shorten_urls_pkey"), file: Some("nbtinsert.c"), line: Some(673), routine: Some("_bt_check_unique") }))
-    pub async fn shorten(&self, url: &str) -> Result<String, ShortenError> {
+    /// The url cache is sized by `URL_CACHE_CAPACITY`, 0 turns it off. The blocklist
+    /// starts out with the private targets only, see `reload_blocklist`.
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        let cache = NonZeroUsize::new(*URL_CACHE_CAPACITY).map(|capacity| {
+            Arc::new(UrlCache::new(
+                capacity,
+                Duration::from_secs(*URL_CACHE_TTL_SECS),
+                Duration::from_secs(*URL_CACHE_NEGATIVE_TTL_SECS),
+            ))
+        });
+        Self {
+            storage,
+            cache,
+            ids: Arc::new(IdGenerator::from_env()),
+            draining: Arc::new(AtomicBool::new(false)),
+            metrics: Arc::new(Metrics::new()),
+            blocklist: Arc::new(RwLock::new(Arc::new(if *BLOCK_PRIVATE_TARGETS {
+                Blocklist::private_targets()
+            } else {
+                Blocklist::default()
+            }))),
+            unlock_failures: Arc::new(Lockout::new(
+                *UNLOCK_MAX_FAILURES,
+                Duration::from_secs(*UNLOCK_LOCKOUT_SECS),
+            )),
+        }
+    }
+
+    pub async fn shorten(&self, url: &str, opts: LinkOptions) -> Result<Shortened, ShortenError> {
         info!("short url: {:?}", url);
-        loop {
-            match self.insert_url(url).await {
-                Ok(id) => break Ok(id),
-                Err(ShortenError::DatabaseError(sqlx::Error::Database(err)))
-                    if Some("23505".into()).eq(&err.code()) =>
-                {
-                    warn!("id conflict! error: {:?}", err);
+        let url = normalize_url(url)?;
+        self.blocklist().check(&url)?;
+        let password_hash = match &opts.password {
+            Some(password) => Some(password::hash(password).await?),
+            None => None,
+        };
+        let token = token::generate();
+        let mut new = NewUrl {
+            id: String::new(),
+            url,
+            opts,
+            token_hash: token::hash(&token),
+            password_hash,
+        };
+        if let Some(alias) = &new.opts.alias {
+            validate_alias(alias)?;
+            new.id = alias.clone();
+            let id = self.storage.insert_url(&new).await?;
+            self.invalidate(&id);
+            return Ok(Shortened::new(&new, id, token));
+        }
+        for _ in 0..self.ids.max_retries() {
+            new.id = self.ids.generate();
+            match self.storage.insert_url(&new).await {
+                Ok(id) => {
+                    self.ids.record(false);
+                    self.invalidate(&id);
+                    return Ok(Shortened::new(&new, id, token));
+                }
+                Err(ShortenError::AliasTaken(id)) => {
+                    self.ids.record(true);
+                    self.metrics.id_collisions.inc();
+                    warn!("id conflict! id: {:?}", id);
                 }
-                Err(e) => break Err(e),
+                Err(e) => return Err(e),
+            };
+        }
+        Err(ShortenError::IdSpaceExhausted(self.ids.max_retries()))
+    }
+
+    /// Shorten many urls at once. Every url is validated on its own and the whole batch
+    /// is stored with one multi-row insert per round, rounds are only repeated for
+    /// generated ids that collided. The outer error is reserved for storage failures.
+    pub async fn shorten_batch(
+        &self,
+        urls: Vec<(String, LinkOptions)>,
+    ) -> Result<Vec<Result<Shortened, ShortenError>>, ShortenError> {
+        info!("short {} urls", urls.len());
+        let mut results: Vec<Result<Shortened, ShortenError>> = Vec::with_capacity(urls.len());
+        // index into results -> link waiting to be stored and its token
+        let mut pending: Vec<(usize, NewUrl, String)> = Vec::new();
+        let blocklist = self.blocklist();
+        for (i, (url, opts)) in urls.into_iter().enumerate() {
+            let checked = normalize_url(&url)
+                .and_then(|url| blocklist.check(&url).map(|_| url))
+                .and_then(|url| match &opts.alias {
+                    Some(alias) => validate_alias(alias).map(|_| (alias.clone(), url)),
+                    None => Ok((self.ids.generate(), url)),
+                });
+            let checked = match (checked, &opts.password) {
+                (Ok(parts), Some(password)) => password::hash(password)
+                    .await
+                    .map(|hash| (parts, Some(hash))),
+                (checked, _) => checked.map(|parts| (parts, None)),
             };
+            match checked {
+                Ok(((id, url), password_hash)) => {
+                    let token = token::generate();
+                    let token_hash = token::hash(&token);
+                    results.push(Err(ShortenError::IdSpaceExhausted(self.ids.max_retries())));
+                    pending.push((
+                        i,
+                        NewUrl {
+                            id,
+                            url,
+                            opts,
+                            token_hash,
+                            password_hash,
+                        },
+                        token,
+                    ));
+                }
+                Err(e) => results.push(Err(e)),
+            }
+        }
+
+        for _ in 0..self.ids.max_retries() {
+            if pending.is_empty() {
+                break;
+            }
+            let new_urls: Vec<NewUrl> = pending.iter().map(|(_, new, _)| new.clone()).collect();
+            let ids = self.storage.insert_urls(&new_urls).await?;
+            let mut retry = Vec::new();
+            for ((i, mut new, token), id) in pending.into_iter().zip(ids) {
+                match (id, &new.opts.alias) {
+                    (Some(id), alias) => {
+                        if alias.is_none() {
+                            self.ids.record(false);
+                        }
+                        self.invalidate(&id);
+                        results[i] = Ok(Shortened::new(&new, id, token));
+                    }
+                    (None, Some(alias)) => {
+                        results[i] = Err(ShortenError::AliasTaken(alias.clone()))
+                    }
+                    (None, None) => {
+                        self.ids.record(true);
+                        self.metrics.id_collisions.inc();
+                        warn!("id conflict! id: {:?}", new.id);
+                        new.id = self.ids.generate();
+                        retry.push((i, new, token));
+                    }
+                }
+            }
+            pending = retry;
+        }
+        Ok(results)
+    }
+
+    /// Point a link at a new url, `token` has to be the one handed out when it was created.
+    pub async fn retarget(&self, id: &str, token: &str, url: &str) -> Result<(), ShortenError> {
+        let url = normalize_url(url)?;
+        self.blocklist().check(&url)?;
+        self.authorize(id, token).await?;
+        self.update_url(id, &url).await
+    }
+
+    /// `retarget` without the owner token, for admins.
+    pub async fn admin_retarget(&self, id: &str, url: &str) -> Result<(), ShortenError> {
+        let url = normalize_url(url)?;
+        self.blocklist().check(&url)?;
+        self.update_url(id, &url).await
+    }
+
+    async fn update_url(&self, id: &str, url: &str) -> Result<(), ShortenError> {
+        if !self.storage.update_url(id, url).await? {
+            return Err(ShortenError::Notfound(id.to_string()));
         }
+        info!("retargeted {} to {}", id, url);
+        self.invalidate(id);
+        Ok(())
     }
 
-    async fn insert_url(&self, url: &str) -> Result<String, ShortenError> {
-        info!("url: {} not found, do insert", url);
-        let id = nanoid::nanoid!(6);
-        let row: ShortenUrl =
-            sqlx::query_as("INSERT INTO shorten_urls (id, url) VALUES ($1, $2) ON CONFLICT(url) DO UPDATE SET url = EXCLUDED.url RETURNING *")
-                .bind(id)
-                .bind(url)
-                .fetch_one(&self.db)
-                .await?;
-        Ok(row.id)
+    /// Delete a link, `token` has to be the one handed out when it was created.
+    pub async fn delete(&self, id: &str, token: &str) -> Result<(), ShortenError> {
+        self.authorize(id, token).await?;
+        self.admin_delete(id).await
+    }
+
+    /// `delete` without the owner token, for admins.
+    pub async fn admin_delete(&self, id: &str) -> Result<(), ShortenError> {
+        if !self.storage.delete_url(id).await? {
+            return Err(ShortenError::Notfound(id.to_string()));
+        }
+        info!("deleted {}", id);
+        self.invalidate(id);
+        Ok(())
+    }
+
+    /// The stored row of a link whatever its state, for admins.
+    pub async fn link(&self, id: &str) -> Result<ShortenUrl, ShortenError> {
+        self.storage
+            .get_url(id)
+            .await?
+            .ok_or_else(|| ShortenError::Notfound(id.to_string()))
+    }
+
+    /// One page of links, see `LinkFilter`.
+    pub async fn list(&self, filter: &LinkFilter) -> Result<Vec<ShortenUrl>, ShortenError> {
+        self.storage.list_urls(filter).await
+    }
+
+    /// Every link, one chunk per page read. Pages are separate queries, links changed
+    /// while the export runs may or may not be in it.
+    pub fn export(
+        &self,
+        format: ExportFormat,
+    ) -> impl Stream<Item = Result<Vec<u8>, ShortenError>> + Send + 'static {
+        let storage = self.storage.clone();
+        let first = LinkFilter {
+            limit: EXPORT_PAGE,
+            ..LinkFilter::default()
+        };
+        stream::try_unfold(Some(first), move |filter| {
+            let storage = storage.clone();
+            async move {
+                let Some(mut filter) = filter else {
+                    return Ok(None);
+                };
+                let rows = storage.list_urls(&filter).await?;
+                let chunk = transfer::encode(format, &rows, filter.after.is_none())?;
+                let next = match rows.last() {
+                    Some(last) if rows.len() == EXPORT_PAGE as usize => {
+                        filter.after = Some(last.id.clone());
+                        Some(filter)
+                    }
+                    _ => None,
+                };
+                Ok(Some((chunk, next)))
+            }
+        })
+    }
+
+    /// Store the links of an export, keeping their ids, tokens and timestamps. Rows have
+    /// to pass the same checks as newly shortened links, rows that do not or whose id is
+    /// taken are reported and the rest is stored in one transaction.
+    pub async fn import(
+        &self,
+        format: ExportFormat,
+        data: &[u8],
+        dry_run: bool,
+    ) -> Result<ImportReport, ShortenError> {
+        let blocklist = self.blocklist();
+        let mut failed = Vec::new();
+        let mut numbers = Vec::new();
+        let mut rows = Vec::new();
+        for (n, row) in transfer::decode(format, data) {
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => {
+                    failed.push(ImportFailure::new(n, None, &e));
+                    continue;
+                }
+            };
+            match check_import(&blocklist, &row) {
+                Ok(url) => {
+                    numbers.push(n);
+                    rows.push(ShortenUrl { url, ..row });
+                }
+                Err(e) => failed.push(ImportFailure::new(n, Some(row.id), &e)),
+            }
+        }
+
+        let results = self.storage.import_urls(&rows, dry_run).await?;
+        let mut imported = 0;
+        for ((n, row), result) in numbers.into_iter().zip(rows).zip(results) {
+            let e = match result {
+                Imported::Stored => None,
+                Imported::IdTaken => Some(ShortenError::AliasTaken(row.id.clone())),
+                Imported::SharedUrlTaken => Some(ShortenError::SharedUrlTaken(row.url)),
+            };
+            if let Some(e) = e {
+                failed.push(ImportFailure::new(n, Some(row.id), &e));
+                continue;
+            }
+            imported += 1;
+            if !dry_run {
+                // a cached miss would hide the imported link
+                self.invalidate(&row.id);
+            }
+        }
+        failed.sort_by_key(|failure| failure.row);
+        Ok(ImportReport {
+            dry_run,
+            imported,
+            failed,
+        })
+    }
+
+    /// Take a link down: it answers with `status` (410 or 451) instead of redirecting
+    /// until it is enabled again.
+    pub async fn disable(
+        &self,
+        id: &str,
+        status: u16,
+        reason: Option<&str>,
+    ) -> Result<(), ShortenError> {
+        validate_disable_status(status)?;
+        if !self.storage.disable_url(id, status, reason).await? {
+            return Err(ShortenError::Notfound(id.to_string()));
+        }
+        info!("disabled {} with {}: {:?}", id, status, reason);
+        self.invalidate(id);
+        Ok(())
+    }
+
+    pub async fn enable(&self, id: &str) -> Result<(), ShortenError> {
+        if !self.storage.enable_url(id).await? {
+            return Err(ShortenError::Notfound(id.to_string()));
+        }
+        info!("enabled {}", id);
+        self.invalidate(id);
+        Ok(())
+    }
+
+    pub fn blocklist(&self) -> Arc<Blocklist> {
+        self.blocklist.read().unwrap().clone()
+    }
+
+    pub fn set_blocklist(&self, blocklist: Blocklist) {
+        *self.blocklist.write().unwrap() = Arc::new(blocklist);
+    }
+
+    /// Re-read `BLOCKLIST_FILE` and return how many entries are in effect. A list
+    /// that fails to load leaves the current one in place.
+    pub fn reload_blocklist(&self) -> Result<usize, ShortenError> {
+        let blocklist = Blocklist::load(BLOCKLIST_FILE.as_deref(), *BLOCK_PRIVATE_TARGETS)?;
+        let entries = blocklist.len();
+        self.set_blocklist(blocklist);
+        info!("blocklist loaded with {} entries", entries);
+        Ok(entries)
+    }
+
+    /// Create an api key named `name` and return its secret, which is not stored anywhere.
+    pub async fn create_api_key(&self, name: &str) -> Result<String, ShortenError> {
+        let key = token::generate();
+        self.storage
+            .insert_api_key(name, &token::hash(&key))
+            .await?;
+        info!("created api key {}", name);
+        Ok(key)
+    }
+
+    pub async fn revoke_api_key(&self, name: &str) -> Result<(), ShortenError> {
+        if !self.storage.revoke_api_key(name).await? {
+            return Err(ShortenError::Notfound(name.to_string()));
+        }
+        info!("revoked api key {}", name);
+        Ok(())
+    }
+
+    /// The active api key whose secret is `key`.
+    pub async fn authenticate(&self, key: &str) -> Result<ApiKey, ShortenError> {
+        self.storage
+            .find_api_key(&token::hash(key))
+            .await?
+            .ok_or(ShortenError::Unauthorized)
+    }
+
+    // always ask the storage, a cached row may already be outdated
+    async fn authorize(&self, id: &str, token: &str) -> Result<(), ShortenError> {
+        let row = self
+            .storage
+            .get_url(id)
+            .await?
+            .ok_or_else(|| ShortenError::Notfound(id.to_string()))?;
+        match row.token_hash {
+            Some(hash) if token::verify(token, &hash) => Ok(()),
+            // others were handed the same link, its creator may not pull it from under them
+            None if row.shared => Err(ShortenError::SharedLink(id.to_string())),
+            _ => Err(ShortenError::Forbidden(id.to_string())),
+        }
     }
 
     pub async fn get_url(&self, id: &str) -> Result<String, ShortenError> {
-        let row: Option<ShortenUrl> =
-            sqlx::query_as("SELECT id, url FROM shorten_urls WHERE id = $1")
-                .bind(id)
-                .fetch_optional(&self.db)
-                .await?;
-        match row {
-            Some(row) => Ok(row.url),
+        Ok(self.lookup(id).await?.url)
+    }
+
+    /// Target, timestamps and click count of a link that has not expired. Password
+    /// protected links keep their target to themselves.
+    pub async fn info(&self, id: &str) -> Result<LinkInfo, ShortenError> {
+        let row = self.lookup(id).await?;
+        if row.password_hash.is_some() {
+            return Err(ShortenError::PasswordRequired(id.to_string()));
+        }
+        let clicks = self
+            .storage
+            .daily_clicks(id)
+            .await?
+            .map(|daily| daily.iter().map(|d| d.clicks).sum())
+            .unwrap_or_default();
+        Ok(LinkInfo {
+            id: row.id,
+            url: row.url,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            clicks,
+        })
+    }
+
+    /// The stored row of a link that has neither expired nor been disabled.
+    pub async fn lookup(&self, id: &str) -> Result<ShortenUrl, ShortenError> {
+        match self.fetch_url(id).await? {
+            Some(ShortenUrl {
+                disabled_status: Some(status),
+                ..
+            }) => Err(ShortenError::Disabled(id.to_string(), status as u16)),
+            Some(row)
+                if row
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at <= Utc::now()) =>
+            {
+                Err(ShortenError::Expired(id.to_string()))
+            }
+            Some(row) => Ok(row),
             None => Err(ShortenError::Notfound(id.to_string())),
         }
     }
+
+    /// The row of a link once `password` is proven right, links without a password
+    /// need none. Too many wrong passwords lock the link for a while.
+    pub async fn unlock(&self, id: &str, password: &str) -> Result<ShortenUrl, ShortenError> {
+        let row = self.lookup(id).await?;
+        let Some(hash) = &row.password_hash else {
+            return Ok(row);
+        };
+        self.unlock_failures
+            .try_begin(id)
+            // rounded up, retrying after `Retry-After` must not hit the lockout again
+            .map_err(|wait| ShortenError::Locked((wait.as_secs_f64().ceil() as u64).max(1)))?;
+        if password::verify(password, hash).await {
+            self.unlock_failures.succeed(id);
+            Ok(row)
+        } else {
+            warn!("wrong password for {}", id);
+            Err(ShortenError::WrongPassword(id.to_string()))
+        }
+    }
+
+    // read-through the url cache, misses are cached as well
+    async fn fetch_url(&self, id: &str) -> Result<Option<ShortenUrl>, ShortenError> {
+        let Some(cache) = &self.cache else {
+            return self.storage.get_url(id).await;
+        };
+        if let Some(row) = cache.get(id) {
+            return Ok(row);
+        }
+        let row = self.storage.get_url(id).await?;
+        cache.insert(id, row.clone());
+        Ok(row)
+    }
+
+    // a previous lookup may have cached the id as missing
+    fn invalidate(&self, id: &str) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate(id);
+        }
+    }
+
+    pub async fn record_click(&self, id: &str, click: Click) -> Result<(), ShortenError> {
+        self.storage.record_click(id, click).await
+    }
+
+    /// Total clicks of a link plus one bucket per (UTC) day that saw any.
+    pub async fn stats(&self, id: &str) -> Result<LinkStats, ShortenError> {
+        let daily = self
+            .storage
+            .daily_clicks(id)
+            .await?
+            .ok_or_else(|| ShortenError::Notfound(id.to_string()))?;
+        Ok(LinkStats {
+            id: id.to_string(),
+            total_clicks: daily.iter().map(|d| d.clicks).sum(),
+            daily,
+        })
+    }
+
+    /// Delete every link whose expiry has passed, returning how many rows were removed.
+    pub async fn purge_expired(&self) -> Result<u64, ShortenError> {
+        let purged = self.storage.purge_expired().await?;
+        if let (Some(cache), true) = (&self.cache, purged > 0) {
+            cache.clear();
+        }
+        Ok(purged)
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Prometheus text for `GET /metrics`.
+    pub fn render_metrics(&self) -> String {
+        self.metrics
+            .render(self.ids.length(), self.storage.pool_stats())
+    }
+
+    /// Mark the state as shutting down, see `readiness`.
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether new traffic should be sent here: the database answers and no
+    /// shutdown is in progress.
+    pub async fn readiness(&self) -> Readiness {
+        let draining = self.draining.load(Ordering::Relaxed);
+        let database = match self.storage.ping().await {
+            Ok(()) => "ok".to_string(),
+            Err(e) => {
+                warn!("readiness ping failed: {}", e);
+                e.message()
+            }
+        };
+        Readiness {
+            ready: !draining && database == "ok",
+            draining,
+            database,
+            pool: self.storage.pool_stats(),
+        }
+    }
+
+    /// Close the storage, requests made afterwards fail.
+    pub async fn close(&self) {
+        self.storage.close().await;
+    }
+
+    /// Purge expired links every `period` in the background, forgetting finished
+    /// password lockouts along the way.
+    pub fn spawn_expiry_purge(&self, period: Duration) -> JoinHandle<()> {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match state.purge_expired().await {
+                    Ok(0) => {}
+                    Ok(n) => info!("purged {} expired urls", n),
+                    Err(e) => warn!("purge expired urls failed: {}", e),
+                }
+                state.unlock_failures.prune();
+            }
+        })
+    }
+}
+
+// the normalized url of an imported row, which has to pass what `shorten` checks
+fn check_import(blocklist: &Blocklist, row: &ShortenUrl) -> Result<String, ShortenError> {
+    validate_alias(&row.id)?;
+    let url = normalize_url(&row.url)?;
+    blocklist.check(&url)?;
+    if let Some(status) = row.redirect_status {
+        validate_redirect_status(u16::try_from(status).unwrap_or_default())?;
+    }
+    if let Some(status) = row.disabled_status {
+        validate_disable_status(u16::try_from(status).unwrap_or_default())?;
+    }
+    Ok(url)
 }
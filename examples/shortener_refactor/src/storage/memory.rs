@@ -0,0 +1,316 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::atomic::{AtomicI64, Ordering},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dashmap::{mapref::entry::Entry, DashMap};
+
+use super::{ApiKey, Imported, LinkFilter, NewUrl, ShortenUrl, Storage};
+use crate::{Click, DailyClicks, ShortenError};
+
+/// Keeps everything in process memory, handy for tests and local runs.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    urls: DashMap<String, ShortenUrl>,
+    // url -> id of its shared row, mirrors the partial unique index of the sql backends
+    shared: DashMap<String, String>,
+    clicks: DashMap<String, Vec<(DateTime<Utc>, Click)>>,
+    // key hash -> key, revoked keys stay like the rows of the sql backends
+    api_keys: DashMap<String, StoredKey>,
+    next_api_key_id: AtomicI64,
+}
+
+#[derive(Debug)]
+struct StoredKey {
+    key: ApiKey,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl MemoryStorage {
+    fn insert_new(&self, new: &NewUrl) -> Result<(), ShortenError> {
+        match self.urls.entry(new.id.clone()) {
+            Entry::Occupied(_) => Err(ShortenError::AliasTaken(new.id.clone())),
+            Entry::Vacant(entry) => {
+                entry.insert(ShortenUrl {
+                    id: new.id.clone(),
+                    url: new.url.clone(),
+                    shared: new.opts.is_shared(),
+                    expires_at: new.opts.expires_at,
+                    token_hash: Some(new.token_hash.clone()),
+                    created_at: Some(Utc::now()),
+                    api_key_id: new.opts.api_key_id,
+                    redirect_status: new.opts.redirect_status.map(|status| status as i16),
+                    disabled_status: None,
+                    disabled_reason: None,
+                    password_hash: new.password_hash.clone(),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    // forget `id` as the shared row of `url`, if it is
+    fn unshare(&self, id: &str, url: &str) {
+        self.shared.remove_if(url, |_, shared_id| shared_id == id);
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn insert_url(&self, new: &NewUrl) -> Result<String, ShortenError> {
+        if !new.opts.is_shared() {
+            self.insert_new(new)?;
+            return Ok(new.id.clone());
+        }
+        // locks `shared` before `urls`, like every other method taking both
+        match self.shared.entry(new.url.clone()) {
+            Entry::Occupied(entry) => {
+                if let Some(mut row) = self.urls.get_mut(entry.get()) {
+                    row.token_hash = None;
+                }
+                Ok(entry.get().clone())
+            }
+            Entry::Vacant(entry) => {
+                self.insert_new(new)?;
+                entry.insert(new.id.clone());
+                Ok(new.id.clone())
+            }
+        }
+    }
+
+    async fn insert_urls(&self, urls: &[NewUrl]) -> Result<Vec<Option<String>>, ShortenError> {
+        let mut ids = Vec::with_capacity(urls.len());
+        // shared urls stored earlier in this batch, repeating them keeps their token
+        let mut stored: HashMap<&str, String> = HashMap::new();
+        for new in urls {
+            if let Some(id) = stored
+                .get(new.url.as_str())
+                .filter(|_| new.opts.is_shared())
+            {
+                ids.push(Some(id.clone()));
+                continue;
+            }
+            match self.insert_url(new).await {
+                Ok(id) if id == new.id => {
+                    if new.opts.is_shared() {
+                        stored.insert(&new.url, id.clone());
+                    }
+                    ids.push(Some(id));
+                }
+                Ok(id) => ids.push(Some(id)),
+                Err(ShortenError::AliasTaken(_)) => ids.push(None),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(ids)
+    }
+
+    async fn get_url(&self, id: &str) -> Result<Option<ShortenUrl>, ShortenError> {
+        Ok(self.urls.get(id).map(|row| row.clone()))
+    }
+
+    async fn list_urls(&self, filter: &LinkFilter) -> Result<Vec<ShortenUrl>, ShortenError> {
+        let now = Utc::now();
+        let mut rows: Vec<ShortenUrl> = self
+            .urls
+            .iter()
+            .map(|row| row.clone())
+            .filter(|row| {
+                filter.after.as_ref().is_none_or(|after| &row.id > after)
+                    && filter
+                        .url_contains
+                        .as_ref()
+                        .is_none_or(|part| row.url.contains(part.as_str()))
+                    && filter
+                        .api_key_id
+                        .is_none_or(|id| row.api_key_id == Some(id))
+                    && filter
+                        .created_after
+                        .is_none_or(|t| row.created_at.is_some_and(|c| c > t))
+                    && filter
+                        .created_before
+                        .is_none_or(|t| row.created_at.is_some_and(|c| c < t))
+                    && (!filter.expired || row.expires_at.is_some_and(|e| e <= now))
+                    && (!filter.disabled || row.disabled_status.is_some())
+            })
+            .collect();
+        rows.sort_by(|a, b| a.id.cmp(&b.id));
+        rows.truncate(filter.limit as usize);
+        Ok(rows)
+    }
+
+    async fn import_urls(
+        &self,
+        rows: &[ShortenUrl],
+        dry_run: bool,
+    ) -> Result<Vec<Imported>, ShortenError> {
+        // rows earlier in the import count as stored, even when nothing is kept
+        let mut ids = HashSet::new();
+        let mut shared = HashSet::new();
+        let imported = rows
+            .iter()
+            .map(|row| {
+                if self.urls.contains_key(&row.id) || ids.contains(&row.id) {
+                    return Imported::IdTaken;
+                }
+                if row.shared {
+                    if self.shared.contains_key(&row.url) || shared.contains(&row.url) {
+                        return Imported::SharedUrlTaken;
+                    }
+                    shared.insert(row.url.clone());
+                }
+                ids.insert(row.id.clone());
+                Imported::Stored
+            })
+            .collect::<Vec<_>>();
+        if !dry_run {
+            let stored = rows
+                .iter()
+                .zip(&imported)
+                .filter(|(_, imported)| **imported == Imported::Stored);
+            for (row, _) in stored {
+                if row.shared {
+                    self.shared.insert(row.url.clone(), row.id.clone());
+                }
+                let api_key_id = row
+                    .api_key_id
+                    .filter(|id| self.api_keys.iter().any(|stored| stored.key.id == *id));
+                let created_at = row.created_at.or_else(|| Some(Utc::now()));
+                self.urls.insert(
+                    row.id.clone(),
+                    ShortenUrl {
+                        api_key_id,
+                        created_at,
+                        ..row.clone()
+                    },
+                );
+            }
+        }
+        Ok(imported)
+    }
+
+    async fn update_url(&self, id: &str, url: &str) -> Result<bool, ShortenError> {
+        // `insert_url` locks `shared` before `urls`, so no guard on `urls` may be held
+        // while unsharing
+        let Some(old) = self.urls.get(id).map(|row| row.url.clone()) else {
+            return Ok(false);
+        };
+        self.unshare(id, &old);
+        let Some(mut row) = self.urls.get_mut(id) else {
+            return Ok(false);
+        };
+        row.url = url.to_string();
+        row.shared = false;
+        Ok(true)
+    }
+
+    async fn disable_url(
+        &self,
+        id: &str,
+        status: u16,
+        reason: Option<&str>,
+    ) -> Result<bool, ShortenError> {
+        let Some(mut row) = self.urls.get_mut(id) else {
+            return Ok(false);
+        };
+        row.disabled_status = Some(status as i16);
+        row.disabled_reason = reason.map(String::from);
+        Ok(true)
+    }
+
+    async fn enable_url(&self, id: &str) -> Result<bool, ShortenError> {
+        let Some(mut row) = self.urls.get_mut(id) else {
+            return Ok(false);
+        };
+        row.disabled_status = None;
+        row.disabled_reason = None;
+        Ok(true)
+    }
+
+    async fn delete_url(&self, id: &str) -> Result<bool, ShortenError> {
+        let Some((_, row)) = self.urls.remove(id) else {
+            return Ok(false);
+        };
+        self.unshare(id, &row.url);
+        self.clicks.remove(id);
+        Ok(true)
+    }
+
+    async fn insert_api_key(&self, name: &str, key_hash: &str) -> Result<ApiKey, ShortenError> {
+        if self.api_keys.iter().any(|stored| stored.key.name == name) {
+            return Err(ShortenError::ApiKeyExists(name.to_string()));
+        }
+        let key = ApiKey {
+            id: self.next_api_key_id.fetch_add(1, Ordering::Relaxed) + 1,
+            name: name.to_string(),
+        };
+        let stored = StoredKey {
+            key: key.clone(),
+            revoked_at: None,
+        };
+        self.api_keys.insert(key_hash.to_string(), stored);
+        Ok(key)
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, ShortenError> {
+        Ok(self
+            .api_keys
+            .get(key_hash)
+            .filter(|stored| stored.revoked_at.is_none())
+            .map(|stored| stored.key.clone()))
+    }
+
+    async fn revoke_api_key(&self, name: &str) -> Result<bool, ShortenError> {
+        let mut revoked = false;
+        for mut stored in self.api_keys.iter_mut() {
+            if stored.key.name == name && stored.revoked_at.is_none() {
+                stored.revoked_at = Some(Utc::now());
+                revoked = true;
+            }
+        }
+        Ok(revoked)
+    }
+
+    async fn purge_expired(&self) -> Result<u64, ShortenError> {
+        let now = Utc::now();
+        let before = self.urls.len();
+        self.urls.retain(|id, row| {
+            let expired = row.expires_at.is_some_and(|expires_at| expires_at <= now);
+            if expired {
+                self.clicks.remove(id);
+            }
+            !expired
+        });
+        Ok((before - self.urls.len()) as u64)
+    }
+
+    async fn record_click(&self, id: &str, click: Click) -> Result<(), ShortenError> {
+        if self.urls.contains_key(id) {
+            self.clicks
+                .entry(id.to_string())
+                .or_default()
+                .push((Utc::now(), click));
+        }
+        Ok(())
+    }
+
+    async fn daily_clicks(&self, id: &str) -> Result<Option<Vec<DailyClicks>>, ShortenError> {
+        if !self.urls.contains_key(id) {
+            return Ok(None);
+        }
+        let mut daily = BTreeMap::new();
+        if let Some(clicks) = self.clicks.get(id) {
+            for (clicked_at, _) in clicks.iter() {
+                *daily.entry(clicked_at.date_naive()).or_insert(0) += 1;
+            }
+        }
+        Ok(Some(
+            daily
+                .into_iter()
+                .map(|(day, clicks)| DailyClicks { day, clicks })
+                .collect(),
+        ))
+    }
+}
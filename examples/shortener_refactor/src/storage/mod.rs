@@ -0,0 +1,293 @@
+mod memory;
+mod postgres;
+mod sqlite;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    migrate::{Migrate, Migrator},
+    FromRow,
+};
+use utoipa::ToSchema;
+
+use crate::{Click, DailyClicks, LinkOptions, ShortenError};
+
+pub use memory::MemoryStorage;
+pub use postgres::PgStorage;
+pub use sqlite::SqliteStorage;
+
+#[derive(FromRow, Debug, Clone, Serialize, Deserialize)]
+pub struct ShortenUrl {
+    #[sqlx(default)]
+    pub id: String,
+    #[sqlx(default)]
+    pub url: String,
+    /// Plain links are reused for later requests of the same url.
+    #[sqlx(default)]
+    #[serde(default)]
+    pub shared: bool,
+    #[sqlx(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[sqlx(default)]
+    pub token_hash: Option<String>,
+    #[sqlx(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[sqlx(default)]
+    pub api_key_id: Option<i64>,
+    #[sqlx(default)]
+    pub redirect_status: Option<i16>,
+    /// 410 or 451 while an admin has taken the link down.
+    #[sqlx(default)]
+    pub disabled_status: Option<i16>,
+    #[sqlx(default)]
+    pub disabled_reason: Option<String>,
+    /// Set for links that ask visitors for a password before redirecting.
+    #[sqlx(default)]
+    pub password_hash: Option<String>,
+}
+
+/// Which links `Storage::list_urls` returns, every filter that is set has to match.
+/// Links come ordered by id, a page starts after the id in `after`.
+#[derive(Debug, Clone)]
+pub struct LinkFilter {
+    /// Part of the target url.
+    pub url_contains: Option<String>,
+    pub api_key_id: Option<i64>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    /// Only links whose expiry has passed.
+    pub expired: bool,
+    /// Only links an admin has disabled.
+    pub disabled: bool,
+    pub after: Option<String>,
+    pub limit: u32,
+}
+
+impl Default for LinkFilter {
+    fn default() -> Self {
+        Self {
+            url_contains: None,
+            api_key_id: None,
+            created_after: None,
+            created_before: None,
+            expired: false,
+            disabled: false,
+            after: None,
+            limit: 50,
+        }
+    }
+}
+
+/// What `Storage::import_urls` did with a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Imported {
+    Stored,
+    IdTaken,
+    /// Another link is already the shared one for the url.
+    SharedUrlTaken,
+}
+
+/// An active api key, looked up by the hash of its secret.
+#[derive(FromRow, Debug, Clone)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+}
+
+/// A link about to be stored.
+#[derive(Debug, Clone)]
+pub struct NewUrl {
+    pub id: String,
+    pub url: String,
+    pub opts: LinkOptions,
+    pub token_hash: String,
+    pub password_hash: Option<String>,
+}
+
+/// Where the links live. Backends only store and fetch rows, id generation and
+/// validation stay in `AppState`.
+#[async_trait]
+pub trait Storage: Debug + Send + Sync + 'static {
+    /// Store a link and return the id it ended up under: a shared url that is already
+    /// stored keeps its existing id, but loses its token since the link is now handed
+    /// out to more than one requester. A taken id is reported as `ShortenError::AliasTaken`.
+    async fn insert_url(&self, new: &NewUrl) -> Result<String, ShortenError>;
+
+    /// Store many links with a single multi-row insert. Returns, in input order, the id
+    /// each link ended up under like `insert_url` does, or `None` when its id was taken.
+    /// Shared urls repeated within the batch do not cost their link its token.
+    async fn insert_urls(&self, urls: &[NewUrl]) -> Result<Vec<Option<String>>, ShortenError>;
+
+    async fn get_url(&self, id: &str) -> Result<Option<ShortenUrl>, ShortenError>;
+
+    /// One page of the links matching `filter`, ordered by id.
+    async fn list_urls(&self, filter: &LinkFilter) -> Result<Vec<ShortenUrl>, ShortenError>;
+
+    /// Store rows as given, e.g. ones read from an export, in a single transaction.
+    /// An `api_key_id` this database does not know is dropped, keys are not part of
+    /// an export. Returns, in input order, what became of each row. A dry run reports
+    /// the same without keeping anything.
+    async fn import_urls(
+        &self,
+        rows: &[ShortenUrl],
+        dry_run: bool,
+    ) -> Result<Vec<Imported>, ShortenError>;
+
+    /// Point an existing link at `url`, returning false when it does not exist. The
+    /// link stops being shared, later requests for its old url get a link of their own.
+    async fn update_url(&self, id: &str, url: &str) -> Result<bool, ShortenError>;
+
+    /// Delete a link and its clicks, returning false when it does not exist.
+    async fn delete_url(&self, id: &str) -> Result<bool, ShortenError>;
+
+    /// Take a link down so it answers with `status`, returning false when it does not exist.
+    async fn disable_url(
+        &self,
+        id: &str,
+        status: u16,
+        reason: Option<&str>,
+    ) -> Result<bool, ShortenError>;
+
+    /// Undo `disable_url`, returning false when the link does not exist.
+    async fn enable_url(&self, id: &str) -> Result<bool, ShortenError>;
+
+    /// Store a new api key, a taken name is reported as `ShortenError::ApiKeyExists`.
+    async fn insert_api_key(&self, name: &str, key_hash: &str) -> Result<ApiKey, ShortenError>;
+
+    /// The api key with this hash unless it was revoked.
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, ShortenError>;
+
+    /// Revoke an api key by name, returning false when there is no active key of that name.
+    async fn revoke_api_key(&self, name: &str) -> Result<bool, ShortenError>;
+
+    /// Delete every link whose expiry has passed, returning how many were removed.
+    async fn purge_expired(&self) -> Result<u64, ShortenError>;
+
+    async fn record_click(&self, id: &str, click: Click) -> Result<(), ShortenError>;
+
+    /// Clicks per (UTC) day in ascending order, `None` when the link does not exist.
+    async fn daily_clicks(&self, id: &str) -> Result<Option<Vec<DailyClicks>>, ShortenError>;
+
+    /// Apply the pending migrations, backends without a schema have nothing to do.
+    async fn migrate(&self) -> Result<(), ShortenError> {
+        Ok(())
+    }
+
+    /// Every migration the binary knows about, oldest first.
+    async fn migrations(&self) -> Result<Vec<MigrationStatus>, ShortenError> {
+        Ok(Vec::new())
+    }
+
+    /// Close the connections once the last request is done with them.
+    async fn close(&self) {}
+
+    /// Round trip to the database, backends without one are always reachable.
+    async fn ping(&self) -> Result<(), ShortenError> {
+        Ok(())
+    }
+
+    /// Open and idle connections, `None` for backends without a pool.
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+    /// Applied, but the file changed since.
+    pub modified: bool,
+}
+
+/// Pick a backend from the scheme of `url`: `postgres://`, `sqlite:` or `memory:`.
+pub async fn connect(url: &str) -> Result<Arc<dyn Storage>, ShortenError> {
+    let scheme = url.split(':').next().unwrap_or_default();
+    let storage: Arc<dyn Storage> = match scheme {
+        "postgres" | "postgresql" => Arc::new(PgStorage::connect(url).await?),
+        "sqlite" => Arc::new(SqliteStorage::connect(url).await?),
+        "memory" => Arc::new(MemoryStorage::default()),
+        _ => return Err(ShortenError::UnsupportedDatabase(scheme.to_string())),
+    };
+    Ok(storage)
+}
+
+async fn migration_status<C: Migrate>(
+    conn: &mut C,
+    migrator: &Migrator,
+) -> Result<Vec<MigrationStatus>, ShortenError> {
+    conn.ensure_migrations_table()
+        .await
+        .map_err(sqlx::Error::from)?;
+    let applied: HashMap<i64, _> = conn
+        .list_applied_migrations()
+        .await
+        .map_err(sqlx::Error::from)?
+        .into_iter()
+        .map(|m| (m.version, m.checksum))
+        .collect();
+    Ok(migrator
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied: applied.contains_key(&m.version),
+            modified: applied
+                .get(&m.version)
+                .is_some_and(|checksum| *checksum != m.checksum),
+        })
+        .collect())
+}
+
+// shared urls of a batch that were stored before it, their links are handed out again
+fn deduped_urls<'a>(urls: &'a [NewUrl], inserted: &[String]) -> Vec<&'a str> {
+    let inserted: HashSet<&str> = inserted.iter().map(String::as_str).collect();
+    let inserted_urls: HashSet<&str> = urls
+        .iter()
+        .filter(|new| inserted.contains(new.id.as_str()))
+        .map(|new| new.url.as_str())
+        .collect();
+    urls.iter()
+        .filter(|new| new.opts.is_shared() && !inserted_urls.contains(new.url.as_str()))
+        .map(|new| new.url.as_str())
+        .collect()
+}
+
+// match the rows a multi-row insert returned (and the shared rows that already existed)
+// back to the links that were asked for
+fn resolve_batch(
+    urls: &[NewUrl],
+    inserted: Vec<String>,
+    existing: Vec<ShortenUrl>,
+) -> Vec<Option<String>> {
+    let mut inserted: HashSet<String> = inserted.into_iter().collect();
+    let existing: HashMap<String, String> =
+        existing.into_iter().map(|row| (row.url, row.id)).collect();
+    urls.iter()
+        .map(|new| {
+            // an id can only be inserted once, the first link asking for it got it
+            if inserted.remove(&new.id) {
+                Some(new.id.clone())
+            } else if new.opts.is_shared() {
+                existing.get(&new.url).cloned()
+            } else {
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,373 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{
+    migrate::Migrator,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    QueryBuilder, Sqlite, SqlitePool,
+};
+use tracing::info;
+
+use super::{
+    deduped_urls, migration_status, resolve_batch, ApiKey, Imported, LinkFilter, MigrationStatus,
+    NewUrl, PoolStats, ShortenUrl, Storage,
+};
+use crate::{Click, DailyClicks, ShortenError};
+
+const MAX_CONNECTION: u32 = 4;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+#[derive(Debug, Clone)]
+pub struct SqliteStorage {
+    db: SqlitePool,
+}
+
+impl SqliteStorage {
+    /// Open (or create) the database at `url`. An in-memory database starts out
+    /// empty and nothing else can reach it, so its schema is created right away.
+    pub async fn connect(url: &str) -> Result<Self, ShortenError> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let in_memory = url.contains(":memory:") || url.contains("mode=memory");
+        // every connection to an in-memory database gets a database of its own,
+        // so keep exactly one connection open for the lifetime of the pool
+        let pool = if in_memory {
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .min_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+        } else {
+            SqlitePoolOptions::new().max_connections(MAX_CONNECTION)
+        };
+        let storage = Self {
+            db: pool.connect_with(options).await?,
+        };
+        if in_memory {
+            storage.migrate().await?;
+        }
+        Ok(storage)
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn insert_url(&self, new: &NewUrl) -> Result<String, ShortenError> {
+        let row: Result<ShortenUrl, _> = if new.opts.is_shared() {
+            info!("url: {} not found, do insert", new.url);
+            sqlx::query_as("INSERT INTO shorten_urls (id, url, token_hash, api_key_id, created_at) VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT(url) WHERE shared DO UPDATE SET token_hash = NULL RETURNING *")
+                .bind(&new.id)
+                .bind(&new.url)
+                .bind(&new.token_hash)
+                .bind(new.opts.api_key_id)
+                .bind(Utc::now())
+                .fetch_one(&self.db)
+                .await
+        } else {
+            sqlx::query_as("INSERT INTO shorten_urls (id, url, shared, expires_at, token_hash, api_key_id, created_at, redirect_status, password_hash) VALUES (?1, ?2, FALSE, ?3, ?4, ?5, ?6, ?7, ?8) RETURNING *")
+                .bind(&new.id)
+                .bind(&new.url)
+                .bind(new.opts.expires_at)
+                .bind(&new.token_hash)
+                .bind(new.opts.api_key_id)
+                .bind(Utc::now())
+                .bind(new.opts.redirect_status.map(|status| status as i16))
+                .bind(&new.password_hash)
+                .fetch_one(&self.db)
+                .await
+        };
+        match row {
+            Ok(row) => Ok(row.id),
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                Err(ShortenError::AliasTaken(new.id.clone()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn insert_urls(&self, urls: &[NewUrl]) -> Result<Vec<Option<String>>, ShortenError> {
+        if urls.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT INTO shorten_urls (id, url, shared, expires_at, token_hash, api_key_id, created_at, redirect_status, password_hash) ",
+        );
+        let now = Utc::now();
+        query.push_values(urls, |mut row, new| {
+            row.push_bind(&new.id)
+                .push_bind(&new.url)
+                .push_bind(new.opts.is_shared())
+                .push_bind(new.opts.expires_at)
+                .push_bind(&new.token_hash)
+                .push_bind(new.opts.api_key_id)
+                .push_bind(now)
+                .push_bind(new.opts.redirect_status.map(|status| status as i16))
+                .push_bind(&new.password_hash);
+        });
+        query.push(" ON CONFLICT DO NOTHING RETURNING id");
+        let inserted: Vec<String> = query
+            .build_query_as::<(String,)>()
+            .fetch_all(&self.db)
+            .await?
+            .into_iter()
+            .map(|(id,)| id)
+            .collect();
+
+        let deduped = deduped_urls(urls, &inserted);
+        if !deduped.is_empty() {
+            let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(
+                "UPDATE shorten_urls SET token_hash = NULL WHERE shared AND url IN (",
+            );
+            let mut separated = query.separated(", ");
+            for url in deduped {
+                separated.push_bind(url);
+            }
+            query.push(")");
+            query.build().execute(&self.db).await?;
+        }
+
+        let mut query: QueryBuilder<Sqlite> =
+            QueryBuilder::new("SELECT id, url FROM shorten_urls WHERE shared AND url IN (");
+        let mut separated = query.separated(", ");
+        for new in urls.iter().filter(|new| new.opts.is_shared()) {
+            separated.push_bind(&new.url);
+        }
+        // keep the statement valid when the batch has no shared urls at all
+        separated.push_bind("");
+        query.push(")");
+        let existing = query.build_query_as().fetch_all(&self.db).await?;
+        Ok(resolve_batch(urls, inserted, existing))
+    }
+
+    async fn get_url(&self, id: &str) -> Result<Option<ShortenUrl>, ShortenError> {
+        let row = sqlx::query_as(
+            "SELECT id, url, shared, expires_at, token_hash, created_at, api_key_id, redirect_status, disabled_status, disabled_reason, password_hash FROM shorten_urls WHERE id = ?1",
+        )
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(row)
+    }
+
+    async fn list_urls(&self, filter: &LinkFilter) -> Result<Vec<ShortenUrl>, ShortenError> {
+        let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT id, url, shared, expires_at, token_hash, created_at, api_key_id, redirect_status, disabled_status, disabled_reason, password_hash FROM shorten_urls WHERE TRUE",
+        );
+        if let Some(after) = &filter.after {
+            query.push(" AND id > ").push_bind(after);
+        }
+        if let Some(part) = &filter.url_contains {
+            query.push(" AND instr(url, ").push_bind(part).push(") > 0");
+        }
+        if let Some(api_key_id) = filter.api_key_id {
+            query.push(" AND api_key_id = ").push_bind(api_key_id);
+        }
+        if let Some(created_after) = filter.created_after {
+            query.push(" AND created_at > ").push_bind(created_after);
+        }
+        if let Some(created_before) = filter.created_before {
+            query.push(" AND created_at < ").push_bind(created_before);
+        }
+        if filter.expired {
+            query.push(" AND expires_at <= ").push_bind(Utc::now());
+        }
+        if filter.disabled {
+            query.push(" AND disabled_status IS NOT NULL");
+        }
+        query
+            .push(" ORDER BY id LIMIT ")
+            .push_bind(i64::from(filter.limit));
+        Ok(query.build_query_as().fetch_all(&self.db).await?)
+    }
+
+    async fn import_urls(
+        &self,
+        rows: &[ShortenUrl],
+        dry_run: bool,
+    ) -> Result<Vec<Imported>, ShortenError> {
+        let mut tx = self.db.begin().await?;
+        let mut imported = Vec::with_capacity(rows.len());
+        for row in rows {
+            let result = sqlx::query(
+                "INSERT INTO shorten_urls (id, url, shared, expires_at, token_hash, created_at, api_key_id, redirect_status, disabled_status, disabled_reason, password_hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6, (SELECT id FROM api_keys WHERE id = ?7), ?8, ?9, ?10, ?11) ON CONFLICT DO NOTHING",
+            )
+            .bind(&row.id)
+            .bind(&row.url)
+            .bind(row.shared)
+            .bind(row.expires_at)
+            .bind(&row.token_hash)
+            .bind(row.created_at.unwrap_or_else(Utc::now))
+            .bind(row.api_key_id)
+            .bind(row.redirect_status)
+            .bind(row.disabled_status)
+            .bind(&row.disabled_reason)
+            .bind(&row.password_hash)
+            .execute(&mut *tx)
+            .await?;
+            imported.push(if result.rows_affected() > 0 {
+                Imported::Stored
+            } else if sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM shorten_urls WHERE id = ?1)")
+                .bind(&row.id)
+                .fetch_one(&mut *tx)
+                .await?
+            {
+                Imported::IdTaken
+            } else {
+                Imported::SharedUrlTaken
+            });
+        }
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(imported)
+    }
+
+    async fn update_url(&self, id: &str, url: &str) -> Result<bool, ShortenError> {
+        let result = sqlx::query("UPDATE shorten_urls SET url = ?2, shared = FALSE WHERE id = ?1")
+            .bind(id)
+            .bind(url)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn disable_url(
+        &self,
+        id: &str,
+        status: u16,
+        reason: Option<&str>,
+    ) -> Result<bool, ShortenError> {
+        let result = sqlx::query(
+            "UPDATE shorten_urls SET disabled_status = ?2, disabled_reason = ?3 WHERE id = ?1",
+        )
+        .bind(id)
+        .bind(status as i16)
+        .bind(reason)
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn enable_url(&self, id: &str) -> Result<bool, ShortenError> {
+        let result = sqlx::query(
+            "UPDATE shorten_urls SET disabled_status = NULL, disabled_reason = NULL WHERE id = ?1",
+        )
+        .bind(id)
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_url(&self, id: &str) -> Result<bool, ShortenError> {
+        let result = sqlx::query("DELETE FROM shorten_urls WHERE id = ?1")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn insert_api_key(&self, name: &str, key_hash: &str) -> Result<ApiKey, ShortenError> {
+        let row = sqlx::query_as(
+            "INSERT INTO api_keys (name, key_hash, created_at) VALUES (?1, ?2, ?3) RETURNING id, name",
+        )
+        .bind(name)
+        .bind(key_hash)
+        .bind(Utc::now())
+        .fetch_one(&self.db)
+        .await;
+        match row {
+            Ok(key) => Ok(key),
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                Err(ShortenError::ApiKeyExists(name.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, ShortenError> {
+        let key = sqlx::query_as(
+            "SELECT id, name FROM api_keys WHERE key_hash = ?1 AND revoked_at IS NULL",
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(key)
+    }
+
+    async fn revoke_api_key(&self, name: &str) -> Result<bool, ShortenError> {
+        let result = sqlx::query(
+            "UPDATE api_keys SET revoked_at = ?2 WHERE name = ?1 AND revoked_at IS NULL",
+        )
+        .bind(name)
+        .bind(Utc::now())
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn purge_expired(&self) -> Result<u64, ShortenError> {
+        let result = sqlx::query("DELETE FROM shorten_urls WHERE expires_at <= ?1")
+            .bind(Utc::now())
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn record_click(&self, id: &str, click: Click) -> Result<(), ShortenError> {
+        sqlx::query("INSERT INTO shorten_clicks (link_id, clicked_at, referrer, user_agent, client_ip) VALUES (?1, ?2, ?3, ?4, ?5)")
+            .bind(id)
+            .bind(Utc::now())
+            .bind(click.referrer)
+            .bind(click.user_agent)
+            .bind(click.client_ip.map(|ip| ip.to_string()))
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn daily_clicks(&self, id: &str) -> Result<Option<Vec<DailyClicks>>, ShortenError> {
+        let exists: Option<(String,)> = sqlx::query_as("SELECT id FROM shorten_urls WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+        let daily = sqlx::query_as(
+            "SELECT date(clicked_at) AS day, COUNT(*) AS clicks FROM shorten_clicks WHERE link_id = ?1 GROUP BY day ORDER BY day",
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(Some(daily))
+    }
+
+    async fn migrate(&self) -> Result<(), ShortenError> {
+        MIGRATOR.run(&self.db).await.map_err(sqlx::Error::from)?;
+        Ok(())
+    }
+
+    async fn migrations(&self) -> Result<Vec<MigrationStatus>, ShortenError> {
+        let mut conn = self.db.acquire().await?;
+        migration_status(&mut *conn, &MIGRATOR).await
+    }
+
+    async fn close(&self) {
+        self.db.close().await;
+    }
+
+    async fn ping(&self) -> Result<(), ShortenError> {
+        sqlx::query("SELECT 1").execute(&self.db).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats {
+            size: self.db.size(),
+            idle: self.db.num_idle(),
+        })
+    }
+}
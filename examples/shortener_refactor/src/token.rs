@@ -0,0 +1,17 @@
+//! Secrets handed out to clients. They are random and long enough that a plain
+//! blake3 hash is sufficient for storing them.
+
+const TOKEN_LEN: usize = 32;
+
+pub(crate) fn generate() -> String {
+    nanoid::nanoid!(TOKEN_LEN)
+}
+
+pub(crate) fn hash(token: &str) -> String {
+    blake3::hash(token.as_bytes()).to_hex().to_string()
+}
+
+/// Compare in constant time, `blake3::Hash` equality does not short-circuit.
+pub(crate) fn verify(token: &str, hash: &str) -> bool {
+    blake3::Hash::from_hex(hash).is_ok_and(|hash| hash == blake3::hash(token.as_bytes()))
+}
@@ -0,0 +1,129 @@
+use std::io::BufRead;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{ShortenError, ShortenUrl};
+
+/// Rows read per query while exporting.
+pub(crate) const EXPORT_PAGE: u32 = 1000;
+
+/// How links are written by an export and read by an import. Every column of
+/// `shorten_urls` is included, hashes too, so links keep working after a move.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    /// One json object per line.
+    #[default]
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    /// `csv` for `*.csv`, json lines otherwise.
+    pub fn from_path(path: &str) -> Self {
+        if path.to_ascii_lowercase().ends_with(".csv") {
+            ExportFormat::Csv
+        } else {
+            ExportFormat::Jsonl
+        }
+    }
+}
+
+/// Outcome of an import. Nothing is stored on a dry run, the report tells what would be.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub imported: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+/// A row that was not imported. `row` counts data rows from 1, the csv header is not one.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportFailure {
+    pub row: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub code: &'static str,
+    pub error: String,
+}
+
+impl ImportFailure {
+    pub(crate) fn new(row: usize, id: Option<String>, error: &ShortenError) -> Self {
+        Self {
+            row,
+            id,
+            code: error.code(),
+            error: error.message(),
+        }
+    }
+}
+
+/// One chunk of an export, the csv header goes in front of the first one.
+pub(crate) fn encode(
+    format: ExportFormat,
+    rows: &[ShortenUrl],
+    first: bool,
+) -> Result<Vec<u8>, ShortenError> {
+    let failed = |e: &dyn std::fmt::Display| ShortenError::ExportFailed(e.to_string());
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(first)
+                .from_writer(Vec::new());
+            for row in rows {
+                writer.serialize(row).map_err(|e| failed(&e))?;
+            }
+            writer.into_inner().map_err(|e| failed(&e))
+        }
+        ExportFormat::Jsonl => {
+            let mut out = Vec::new();
+            for row in rows {
+                serde_json::to_writer(&mut out, row).map_err(|e| failed(&e))?;
+                out.push(b'\n');
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Every row of an import with its number, rows that cannot be read are errors of their own.
+pub(crate) fn decode(
+    format: ExportFormat,
+    data: &[u8],
+) -> Vec<(usize, Result<ShortenUrl, ShortenError>)> {
+    let invalid = |e: &dyn std::fmt::Display| ShortenError::InvalidRow(e.to_string());
+    match format {
+        ExportFormat::Csv => csv::Reader::from_reader(data)
+            .deserialize()
+            .enumerate()
+            .map(|(i, row)| (i + 1, row.map_err(|e| invalid(&e))))
+            .collect(),
+        ExportFormat::Jsonl => data
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .map(|(i, line)| {
+                let row = line
+                    .map_err(|e| invalid(&e))
+                    .and_then(|line| serde_json::from_str(&line).map_err(|e| invalid(&e)));
+                (i + 1, row)
+            })
+            .collect(),
+    }
+}
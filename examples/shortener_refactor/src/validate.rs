@@ -0,0 +1,96 @@
+use http::HeaderValue;
+use url::Url;
+
+use crate::ShortenError;
+
+pub(crate) const ALIAS_MIN_LEN: usize = 3;
+pub(crate) const ALIAS_MAX_LEN: usize = 32;
+const URL_MAX_LEN: usize = 2048;
+pub(crate) const REDIRECT_STATUSES: &[u16] = &[301, 302, 307, 308];
+pub(crate) const DISABLE_STATUSES: &[u16] = &[410, 451];
+
+/// Words that are (or may become) paths served by the shortener itself, so they
+/// can never be handed out as short ids.
+const RESERVED_IDS: &[&str] = &[
+    "admin", "api", "assets", "batch", "docs", "healthz", "metrics", "readyz", "static",
+];
+
+pub(crate) fn is_reserved(id: &str) -> bool {
+    RESERVED_IDS
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(id))
+}
+
+pub(crate) fn validate_redirect_status(status: u16) -> Result<(), ShortenError> {
+    if REDIRECT_STATUSES.contains(&status) {
+        Ok(())
+    } else {
+        Err(ShortenError::InvalidRedirectStatus(status))
+    }
+}
+
+pub(crate) fn validate_disable_status(status: u16) -> Result<(), ShortenError> {
+    if DISABLE_STATUSES.contains(&status) {
+        Ok(())
+    } else {
+        Err(ShortenError::InvalidDisableStatus(status))
+    }
+}
+
+/// Check a user supplied alias: 3-32 characters of `[A-Za-z0-9_-]`, not a reserved word.
+pub(crate) fn validate_alias(alias: &str) -> Result<(), ShortenError> {
+    if !(ALIAS_MIN_LEN..=ALIAS_MAX_LEN).contains(&alias.len()) {
+        return Err(ShortenError::InvalidAlias(format!(
+            "{} must be between {} and {} characters",
+            alias, ALIAS_MIN_LEN, ALIAS_MAX_LEN
+        )));
+    }
+    if !alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(ShortenError::InvalidAlias(format!(
+            "{} may only contain letters, digits, '_' and '-'",
+            alias
+        )));
+    }
+    if is_reserved(alias) {
+        return Err(ShortenError::InvalidAlias(format!("{} is reserved", alias)));
+    }
+    Ok(())
+}
+
+/// Parse a target url and return its normalized form: only absolute http(s) urls with a
+/// host are accepted, scheme and host are lowercased and a default port is dropped, so
+/// equivalent urls end up as the same string.
+pub(crate) fn normalize_url(raw: &str) -> Result<String, ShortenError> {
+    let raw = raw.trim();
+    if raw.len() > URL_MAX_LEN {
+        return Err(ShortenError::InvalidUrl(format!(
+            "url must not be longer than {} characters",
+            URL_MAX_LEN
+        )));
+    }
+    let url = Url::parse(raw).map_err(|e| ShortenError::InvalidUrl(format!("{}: {}", raw, e)))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ShortenError::InvalidUrl(format!(
+            "{}: only http and https urls can be shortened",
+            raw
+        )));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(ShortenError::InvalidUrl(format!(
+            "{}: host is required",
+            raw
+        )));
+    }
+    let url = String::from(url);
+    // percent-encoding may have grown the url, and it has to fit in a Location header
+    if url.len() > URL_MAX_LEN || HeaderValue::from_str(&url).is_err() {
+        return Err(ShortenError::InvalidUrl(format!(
+            "{}: cannot be used as a redirect target",
+            raw
+        )));
+    }
+    Ok(url)
+}
@@ -0,0 +1,678 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use axum::{body::Body, extract::connect_info::MockConnectInfo, Router};
+use http::{Request, Response, StatusCode};
+use serde_json::Value;
+use shortener_refactor::{
+    app, AppState, LinkOptions, MemoryStorage, ShortenUrl, SqliteStorage, Storage, API_KEY_HEADER,
+    CREATE_RATE_PER_MIN, MAX_BATCH_SIZE, REDIRECT_RATE_PER_MIN, REQUEST_ID_HEADER,
+};
+use tower::ServiceExt;
+use url::Url;
+
+async fn state() -> Result<AppState> {
+    let state = AppState::new(Arc::new(MemoryStorage::default()));
+    let opts = LinkOptions {
+        alias: Some("rust".to_string()),
+        ..Default::default()
+    };
+    state.shorten("https://www.rust-lang.org/", opts).await?;
+    Ok(state)
+}
+
+fn router(state: AppState) -> Router {
+    app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))))
+}
+
+async fn body(res: Response<Body>) -> Result<String> {
+    let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+// path the form on `page` posts to
+fn form_action(page: &str) -> Result<String> {
+    let action = page
+        .split("action=\"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .ok_or_else(|| anyhow::anyhow!("no form in {}", page))?;
+    Ok(Url::parse(action)?.path().to_string())
+}
+
+// the token admin routes expect, set before anything reads `ADMIN_TOKEN`
+fn admin_token() -> &'static str {
+    const TOKEN: &str = "http-test-admin-token";
+    std::env::set_var("ADMIN_TOKEN", TOKEN);
+    TOKEN
+}
+
+// a link written straight to the storage, as rows from before urls were validated
+async fn legacy_state(id: &str, url: &str) -> Result<AppState> {
+    let storage = Arc::new(MemoryStorage::default());
+    let row = ShortenUrl {
+        id: id.to_string(),
+        url: url.to_string(),
+        shared: false,
+        expires_at: None,
+        token_hash: None,
+        created_at: None,
+        api_key_id: None,
+        redirect_status: None,
+        disabled_status: None,
+        disabled_reason: None,
+        password_hash: None,
+    };
+    storage.import_urls(&[row], false).await?;
+    Ok(AppState::new(storage))
+}
+
+fn shorten_request(key: &str, json: &str) -> Result<Request<Body>> {
+    Ok(Request::post("/")
+        .header(API_KEY_HEADER, key)
+        .header("accept", "application/json")
+        .header("content-type", "application/json")
+        .body(Body::from(json.to_string()))?)
+}
+
+// path of the short link in a shorten response
+async fn link_path(res: Response<Body>) -> Result<String> {
+    let created: Value = serde_json::from_str(&body(res).await?)?;
+    let url = created["url"].as_str().unwrap_or_default();
+    Ok(Url::parse(url)?.path().to_string())
+}
+
+async fn send(router: &Router, req: Request<Body>) -> Result<Response<Body>> {
+    Ok(router.clone().oneshot(req).await?)
+}
+
+#[tokio::test]
+async fn redirects_should_be_limited_per_ip_whatever_the_api_key() -> Result<()> {
+    let router = router(state().await?);
+    let mut limited = 0;
+    // twice the budget, a few tokens refill while the requests run
+    for n in 0..*REDIRECT_RATE_PER_MIN * 2 {
+        let req = Request::get("/rust")
+            .header(API_KEY_HEADER, format!("made-up-{}", n))
+            .body(Body::empty())?;
+        if send(&router, req).await?.status() == StatusCode::TOO_MANY_REQUESTS {
+            limited += 1;
+        }
+    }
+    assert!(
+        limited >= *REDIRECT_RATE_PER_MIN / 2,
+        "only {} redirects were limited",
+        limited
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_valid_api_keys_should_get_a_bucket_of_their_own() -> Result<()> {
+    let state = state().await?;
+    let key = state.create_api_key("limits").await?;
+    let router = router(state);
+    let shorten = |key: Option<&str>| {
+        let mut req = Request::post("/").header("content-type", "application/json");
+        if let Some(key) = key {
+            req = req.header(API_KEY_HEADER, key);
+        }
+        req.body(Body::from(r#"{"url":"https://crates.io/"}"#))
+    };
+
+    // a valid key spends its own budget, not the one of the ip
+    for _ in 0..*CREATE_RATE_PER_MIN {
+        let res = send(&router, shorten(Some(&key))?).await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+    let res = send(&router, shorten(Some(&key))?).await?;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    // shortening needs a key, but the ip still has budget to be told so
+    let res = send(&router, shorten(None)?).await?;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // made up keys are rejected and use up the budget of the ip
+    let mut statuses = Vec::new();
+    // one more than the budget left, a token may have refilled meanwhile
+    for n in 0..*CREATE_RATE_PER_MIN + 1 {
+        let key = format!("made-up-{}", n);
+        statuses.push(send(&router, shorten(Some(&key))?).await?.status());
+    }
+    let rejected = statuses
+        .iter()
+        .take_while(|s| **s == StatusCode::UNAUTHORIZED)
+        .count();
+    assert!(
+        rejected >= (*CREATE_RATE_PER_MIN - 1) as usize,
+        "{:?}",
+        statuses
+    );
+    assert!(statuses[rejected..]
+        .iter()
+        .all(|s| *s == StatusCode::TOO_MANY_REQUESTS));
+    assert_eq!(statuses.last(), Some(&StatusCode::TOO_MANY_REQUESTS));
+    Ok(())
+}
+
+#[tokio::test]
+async fn a_wrong_password_should_leave_a_working_form() -> Result<()> {
+    let state = state().await?;
+    let opts = LinkOptions {
+        alias: Some("sec".to_string()),
+        password: Some("hunter2".to_string()),
+        ..Default::default()
+    };
+    state.shorten("https://crates.io/", opts).await?;
+    let router = router(state);
+    let unlock = |action: &str, password: &str| {
+        Request::post(action)
+            .header("host", "sho.rt")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(Body::from(format!("password={}", password)))
+    };
+
+    let res = send(
+        &router,
+        Request::get("/sec")
+            .header("host", "sho.rt")
+            .body(Body::empty())?,
+    )
+    .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let action = form_action(&body(res).await?)?;
+    assert_eq!(action, "/sec/unlock");
+
+    let res = send(&router, unlock(&action, "wrong")?).await?;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let action = form_action(&body(res).await?)?;
+    assert_eq!(action, "/sec/unlock");
+
+    let res = send(&router, unlock(&action, "hunter2")?).await?;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(res.headers()["location"], "https://crates.io/");
+    Ok(())
+}
+
+#[tokio::test]
+async fn malformed_unlock_forms_should_get_coded_errors() -> Result<()> {
+    let state = state().await?;
+    let opts = LinkOptions {
+        alias: Some("sec".to_string()),
+        password: Some("hunter2".to_string()),
+        ..Default::default()
+    };
+    state.shorten("https://crates.io/", opts).await?;
+    let router = router(state);
+    for (content_type, status) in [
+        (
+            "application/x-www-form-urlencoded",
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+        ("text/plain", StatusCode::UNSUPPORTED_MEDIA_TYPE),
+    ] {
+        let req = Request::post("/sec/unlock")
+            .header("accept", "application/json")
+            .header("content-type", content_type)
+            .body(Body::from("pass=hunter2"))?;
+        let res = send(&router, req).await?;
+        assert_eq!(res.status(), status);
+        let error: Value = serde_json::from_str(&body(res).await?)?;
+        assert_eq!(error["code"], "invalid_input");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn json_errors_should_carry_the_request_id() -> Result<()> {
+    let router = router(state().await?);
+    let req = Request::get("/missing")
+        .header("accept", "application/json")
+        .header(REQUEST_ID_HEADER, "req-42")
+        .body(Body::empty())?;
+    let res = send(&router, req).await?;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(res.headers()[REQUEST_ID_HEADER], "req-42");
+    assert_eq!(res.headers()["content-type"], "application/json");
+    let error: Value = serde_json::from_str(&body(res).await?)?;
+    assert_eq!(error["code"], "not_found");
+    assert!(error["message"]
+        .as_str()
+        .is_some_and(|m| m.contains("missing")));
+    assert_eq!(error["request_id"], "req-42");
+
+    // without one the generated id is the same in header and body
+    let req = Request::get("/missing")
+        .header("accept", "application/json")
+        .body(Body::empty())?;
+    let res = send(&router, req).await?;
+    let request_id = res.headers()[REQUEST_ID_HEADER].to_str()?.to_string();
+    let error: Value = serde_json::from_str(&body(res).await?)?;
+    assert_eq!(error["request_id"], request_id.as_str());
+
+    // browsers keep getting html
+    let req = Request::get("/missing")
+        .header("accept", "text/html,application/json")
+        .body(Body::empty())?;
+    let res = send(&router, req).await?;
+    assert!(res.headers()["content-type"]
+        .to_str()?
+        .starts_with("text/html"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn internal_errors_should_hide_their_message() -> Result<()> {
+    let state = AppState::new(Arc::new(SqliteStorage::connect("sqlite::memory:").await?));
+    state.close().await;
+    let router = router(state);
+    let req = Request::get("/anything")
+        .header("accept", "application/json")
+        .body(Body::empty())?;
+    let res = send(&router, req).await?;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let error: Value = serde_json::from_str(&body(res).await?)?;
+    assert_eq!(error["code"], "internal_error");
+    assert_eq!(error["message"], "internal server error");
+    Ok(())
+}
+
+#[tokio::test]
+async fn qr_codes_should_come_as_png_or_svg() -> Result<()> {
+    let router = router(state().await?);
+    let qr = |uri: &str, accept: &str| {
+        Request::get(uri)
+            .header("host", "sho.rt")
+            .header("accept", accept)
+            .body(Body::empty())
+    };
+
+    let res = send(&router, qr("/rust/qr", "*/*")?).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    let png = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+    assert!(png.starts_with(b"\x89PNG"));
+
+    for res in [
+        send(&router, qr("/rust/qr?format=svg", "*/*")?).await?,
+        send(&router, qr("/rust/qr", "image/svg+xml")?).await?,
+    ] {
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "image/svg+xml");
+        assert!(body(res).await?.contains("<svg"));
+    }
+
+    let res = send(&router, qr("/missing/qr", "*/*")?).await?;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = send(&router, qr("/rust/qr?size=big", "application/json")?).await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let error: Value = serde_json::from_str(&body(res).await?)?;
+    assert_eq!(error["code"], "invalid_input");
+    Ok(())
+}
+
+#[tokio::test]
+async fn qr_sizes_should_be_capped() -> Result<()> {
+    let router = router(state().await?);
+    let png = |size: u32| {
+        let req = Request::get(format!("/rust/qr?size={}", size))
+            .header("host", "sho.rt")
+            .body(Body::empty());
+        let router = router.clone();
+        async move {
+            let res = send(&router, req?).await?;
+            assert_eq!(res.status(), StatusCode::OK);
+            Ok::<_, anyhow::Error>(axum::body::to_bytes(res.into_body(), usize::MAX).await?)
+        }
+    };
+    // 2048 is the largest size rendered
+    let largest = png(2048).await?;
+    assert_eq!(png(100_000).await?, largest);
+    assert_ne!(png(1024).await?, largest);
+    Ok(())
+}
+
+#[tokio::test]
+async fn metrics_should_count_redirects() -> Result<()> {
+    let router = router(state().await?);
+    let res = send(&router, Request::get("/rust").body(Body::empty())?).await?;
+    assert!(res.status().is_redirection());
+    let res = send(&router, Request::get("/missing").body(Body::empty())?).await?;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = send(&router, Request::get("/metrics").body(Body::empty())?).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let metrics = body(res).await?;
+    assert!(
+        metrics.contains(r#"redirects_total{result="hit"} 1"#),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains(r#"redirects_total{result="miss"} 1"#),
+        "{}",
+        metrics
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn unusable_stored_urls_should_be_a_server_error() -> Result<()> {
+    let router = router(legacy_state("broken", "https://example.com/\nbroken").await?);
+    let req = Request::get("/broken")
+        .header("accept", "application/json")
+        .body(Body::empty())?;
+    let res = send(&router, req).await?;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let error: Value = serde_json::from_str(&body(res).await?)?;
+    assert_eq!(error["code"], "internal_error");
+    Ok(())
+}
+
+#[tokio::test]
+async fn every_url_of_a_batch_should_count_against_the_budget() -> Result<()> {
+    let state = state().await?;
+    let key = state.create_api_key("batches").await?;
+    let router = router(state);
+    let urls: Vec<String> = (0..*CREATE_RATE_PER_MIN)
+        .map(|n| format!(r#"{{"url":"https://crates.io/crates/{}"}}"#, n))
+        .collect();
+    let req = Request::post("/batch")
+        .header(API_KEY_HEADER, &key)
+        .header("content-type", "application/json")
+        .body(Body::from(format!("[{}]", urls.join(","))))?;
+    assert_eq!(send(&router, req).await?.status(), StatusCode::OK);
+
+    let req = Request::post("/")
+        .header(API_KEY_HEADER, &key)
+        .header("content-type", "application/json")
+        .body(Body::from(r#"{"url":"https://docs.rs/"}"#))?;
+    assert_eq!(
+        send(&router, req).await?.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn retry_after_should_not_end_before_the_budget_refills() -> Result<()> {
+    let router = router(state().await?);
+    let shorten = || {
+        Request::post("/")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"url":"https://crates.io/"}"#))
+    };
+    let mut res = send(&router, shorten()?).await?;
+    while res.status() != StatusCode::TOO_MANY_REQUESTS {
+        res = send(&router, shorten()?).await?;
+    }
+    let wait: u64 = res.headers()["retry-after"].to_str()?.parse()?;
+    tokio::time::sleep(std::time::Duration::from_secs(wait)).await;
+    let res = send(&router, shorten()?).await?;
+    assert_ne!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    Ok(())
+}
+
+#[tokio::test]
+async fn bad_transfer_options_should_get_coded_errors() -> Result<()> {
+    let token = admin_token();
+    let router = router(state().await?);
+    for req in [
+        Request::get("/admin/export?format=xml"),
+        Request::post("/admin/import?format=xml"),
+        Request::post("/admin/import?dry_run=maybe"),
+    ] {
+        let req = req
+            .header("accept", "application/json")
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = send(&router, req).await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let error: Value = serde_json::from_str(&body(res).await?)?;
+        assert_eq!(error["code"], "invalid_input");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn previews_should_not_link_unusable_stored_urls() -> Result<()> {
+    let router = router(legacy_state("legacy", "javascript:alert(1)").await?);
+    let res = send(&router, Request::get("/legacy+").body(Body::empty())?).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let page = body(res).await?;
+    assert!(page.contains("javascript:alert(1)"), "{}", page);
+    assert!(!page.contains("href"), "{}", page);
+    Ok(())
+}
+
+#[tokio::test]
+async fn links_should_redirect_with_their_own_status() -> Result<()> {
+    let state = state().await?;
+    let key = state.create_api_key("statuses").await?;
+    let router = router(state);
+    for status in [301, 302, 307, 308] {
+        let json = format!(
+            r#"{{"url":"https://crates.io/{}","redirect_status":{}}}"#,
+            status, status
+        );
+        let res = send(&router, shorten_request(&key, &json)?).await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let path = link_path(res).await?;
+
+        let res = send(&router, Request::get(&path).body(Body::empty())?).await?;
+        assert_eq!(res.status().as_u16(), status);
+        // temporary redirects must not be remembered
+        let no_store = res
+            .headers()
+            .get("cache-control")
+            .is_some_and(|v| v == "no-store");
+        assert_eq!(no_store, matches!(status, 302 | 307), "{}", status);
+    }
+
+    for status in [200, 303, 404] {
+        let json = format!(
+            r#"{{"url":"https://docs.rs/","redirect_status":{}}}"#,
+            status
+        );
+        let res = send(&router, shorten_request(&key, &json)?).await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let error: Value = serde_json::from_str(&body(res).await?)?;
+        assert_eq!(error["code"], "invalid_redirect_status");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn previews_and_info_should_follow_the_link_state() -> Result<()> {
+    let state = state().await?;
+    let opts = LinkOptions {
+        alias: Some("soon".to_string()),
+        expires_at: Some(chrono::Utc::now() + chrono::TimeDelta::milliseconds(50)),
+        ..Default::default()
+    };
+    state.shorten("https://docs.rs/", opts).await?;
+    let opts = LinkOptions {
+        alias: Some("sec".to_string()),
+        password: Some("hunter2".to_string()),
+        ..Default::default()
+    };
+    state.shorten("https://crates.io/", opts).await?;
+    let router = router(state);
+    let get = |uri: &str| {
+        Request::get(uri)
+            .header("accept", "application/json")
+            .body(Body::empty())
+    };
+
+    let res = send(&router, get("/rust+")?).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("location").is_none());
+    let page = body(res).await?;
+    assert!(
+        page.contains(r#"href="https://www.rust-lang.org/""#),
+        "{}",
+        page
+    );
+    let res = send(&router, get("/rust/info")?).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let info: Value = serde_json::from_str(&body(res).await?)?;
+    assert_eq!(info["id"], "rust");
+    assert_eq!(info["url"], "https://www.rust-lang.org/");
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    for uri in ["/soon+", "/soon/info"] {
+        let res = send(&router, get(uri)?).await?;
+        assert_eq!(res.status(), StatusCode::GONE, "{}", uri);
+        let error: Value = serde_json::from_str(&body(res).await?)?;
+        assert_eq!(error["code"], "expired");
+    }
+
+    // the target stays hidden behind the password
+    for uri in ["/sec+", "/sec/info"] {
+        let res = send(&router, get(uri)?).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+        let error = body(res).await?;
+        assert!(!error.contains("crates.io"), "{}", error);
+        let error: Value = serde_json::from_str(&error)?;
+        assert_eq!(error["code"], "password_required");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn admins_should_take_links_down_and_back_up() -> Result<()> {
+    let token = admin_token();
+    let router = router(state().await?);
+    let admin = |uri: &str, bearer: Option<&str>, json: &str| {
+        let mut req = Request::post(uri)
+            .header("accept", "application/json")
+            .header("content-type", "application/json");
+        if let Some(bearer) = bearer {
+            req = req.header("authorization", format!("Bearer {}", bearer));
+        }
+        req.body(Body::from(json.to_string()))
+    };
+    let redirect = || Request::get("/rust").body(Body::empty());
+
+    for bearer in [None, Some("not-the-token")] {
+        let res = send(&router, admin("/admin/links/rust/disable", bearer, "{}")?).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let error: Value = serde_json::from_str(&body(res).await?)?;
+        assert_eq!(error["code"], "admin_required");
+    }
+    assert!(send(&router, redirect()?).await?.status().is_redirection());
+
+    let res = send(
+        &router,
+        admin("/admin/links/rust/disable", Some(token), "{}")?,
+    )
+    .await?;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    let res = send(&router, redirect()?).await?;
+    assert_eq!(res.status(), StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS);
+
+    let json = r#"{"status":410,"reason":"phishing"}"#;
+    let res = send(
+        &router,
+        admin("/admin/links/rust/disable", Some(token), json)?,
+    )
+    .await?;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    let res = send(&router, redirect()?).await?;
+    assert_eq!(res.status(), StatusCode::GONE);
+
+    let res = send(
+        &router,
+        admin("/admin/links/rust/enable", Some("wrong"), "")?,
+    )
+    .await?;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = send(&router, admin("/admin/links/rust/enable", Some(token), "")?).await?;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    let res = send(&router, redirect()?).await?;
+    assert!(res.status().is_redirection());
+    assert_eq!(res.headers()["location"], "https://www.rust-lang.org/");
+    Ok(())
+}
+
+#[tokio::test]
+async fn blocklist_reloads_should_apply_to_new_links() -> Result<()> {
+    let token = admin_token();
+    let file = std::env::temp_dir().join(format!("blocklist-{}.txt", std::process::id()));
+    std::fs::write(&file, "evil.example\n")?;
+    // only read by the reload below
+    std::env::set_var("BLOCKLIST_FILE", &file);
+    let state = state().await?;
+    let key = state.create_api_key("blocked").await?;
+    let router = router(state);
+    let shorten = |url: &str| shorten_request(&key, &format!(r#"{{"url":"{}"}}"#, url));
+
+    let res = send(&router, shorten("https://evil.example/login")?).await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    let req = Request::post("/admin/blocklist/reload").body(Body::empty())?;
+    assert_eq!(send(&router, req).await?.status(), StatusCode::UNAUTHORIZED);
+    let req = Request::post("/admin/blocklist/reload")
+        .header("authorization", format!("Bearer {}", token))
+        .body(Body::empty())?;
+    let res = send(&router, req).await?;
+    std::fs::remove_file(&file)?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let reloaded: Value = serde_json::from_str(&body(res).await?)?;
+    assert!(reloaded["entries"].as_u64().is_some_and(|n| n >= 1));
+
+    for url in ["https://evil.example/account", "http://127.0.0.1:8080/"] {
+        let res = send(&router, shorten(url)?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN, "{}", url);
+        let error: Value = serde_json::from_str(&body(res).await?)?;
+        assert_eq!(error["code"], "blocked_url");
+    }
+    let res = send(&router, shorten("https://crates.io/")?).await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    Ok(())
+}
+
+#[tokio::test]
+async fn batches_should_report_every_url_on_its_own() -> Result<()> {
+    let state = state().await?;
+    let key = state.create_api_key("batch").await?;
+    let router = router(state);
+    let batch = |json: String| {
+        Request::post("/batch")
+            .header(API_KEY_HEADER, &key)
+            .header("accept", "application/json")
+            .header("content-type", "application/json")
+            .body(Body::from(json))
+    };
+
+    let json = r#"[
+        {"url":"https://crates.io/"},
+        {"url":"not a url"},
+        {"url":"https://docs.rs/","alias":"rust"},
+        {"url":"https://docs.rs/","ttl_seconds":60,"expires_at":"2099-01-01T00:00:00Z"},
+        {"url":"https://docs.rs/","alias":"docs-rs"}
+    ]"#;
+    let res = send(&router, batch(json.to_string())?).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let results: Value = serde_json::from_str(&body(res).await?)?;
+    let results = results.as_array().unwrap();
+    assert_eq!(results.len(), 5);
+    assert!(results[0]["id"].is_string() && results[0]["token"].is_string());
+    assert_eq!(results[1]["status"], 400);
+    assert_eq!(results[1]["code"], "invalid_url");
+    assert_eq!(results[2]["status"], 409);
+    assert_eq!(results[2]["code"], "alias_taken");
+    assert_eq!(results[3]["status"], 400);
+    assert_eq!(results[3]["code"], "invalid_expiry");
+    assert_eq!(results[4]["id"], "docs-rs");
+    assert!(results[4]["url"]
+        .as_str()
+        .is_some_and(|url| url.ends_with("/docs-rs")));
+
+    let urls = vec![r#"{"url":"https://crates.io/"}"#; *MAX_BATCH_SIZE + 1];
+    let res = send(&router, batch(format!("[{}]", urls.join(",")))?).await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let error: Value = serde_json::from_str(&body(res).await?)?;
+    assert_eq!(error["code"], "batch_too_large");
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use axum::{body::Body, extract::connect_info::MockConnectInfo, Router};
+use http::{Method, Request, StatusCode};
+use shortener_refactor::{app, routes, ApiDoc, AppState, LinkOptions, MemoryStorage};
+use tower::ServiceExt;
+use utoipa::OpenApi;
+
+// served for reading the spec, not described by it
+const UNDOCUMENTED: &[&str] = &[
+    "/openapi.json",
+    "/docs",
+    "/docs/swagger-ui-bundle.js",
+    "/docs/swagger-ui.css",
+];
+const METHODS: &[Method] = &[
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+];
+
+async fn router() -> Result<Router> {
+    let state = AppState::new(Arc::new(MemoryStorage::default()));
+    let opts = LinkOptions {
+        alias: Some("spec".to_string()),
+        ..Default::default()
+    };
+    state.shorten("https://www.rust-lang.org/", opts).await?;
+    Ok(app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000)))))
+}
+
+fn documented() -> BTreeMap<String, Vec<Method>> {
+    let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+    spec["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(path, item)| {
+            let methods = METHODS
+                .iter()
+                .filter(|m| item.get(m.as_str().to_lowercase()).is_some())
+                .cloned()
+                .collect();
+            (path.clone(), methods)
+        })
+        .collect()
+}
+
+// every route `app` serves in openapi syntax, with the methods it answers
+async fn routed() -> Result<BTreeMap<String, Vec<Method>>> {
+    let app = router().await?;
+    let mut routed = BTreeMap::new();
+    for path in routes() {
+        let uri = path.replace(":id", "spec");
+        let mut methods = Vec::new();
+        for method in METHODS {
+            let req = Request::builder()
+                .method(method)
+                .uri(&uri)
+                .body(Body::empty())?;
+            let status = app.clone().oneshot(req).await?.status();
+            if status != StatusCode::NOT_FOUND && status != StatusCode::METHOD_NOT_ALLOWED {
+                methods.push(method.clone());
+            }
+        }
+        assert!(!methods.is_empty(), "{} answers no method", path);
+        routed.insert(path.replace(":id", "{id}"), methods);
+    }
+    Ok(routed)
+}
+
+#[tokio::test]
+async fn openapi_should_document_every_route_and_method() -> Result<()> {
+    let mut routed = routed().await?;
+    for path in UNDOCUMENTED {
+        assert_eq!(routed.remove(*path), Some(vec![Method::GET]), "{}", path);
+    }
+    assert_eq!(routed, documented());
+    Ok(())
+}
+
+#[tokio::test]
+async fn openapi_and_docs_should_be_served() -> Result<()> {
+    let app = router().await?;
+    for uri in UNDOCUMENTED {
+        let req = Request::builder().uri(*uri).body(Body::empty())?;
+        assert_eq!(app.clone().oneshot(req).await?.status(), StatusCode::OK);
+    }
+    // everything the page needs comes from the service itself, relative to it
+    let req = Request::builder().uri("/docs").body(Body::empty())?;
+    let page = axum::body::to_bytes(app.oneshot(req).await?.into_body(), usize::MAX).await?;
+    let page = String::from_utf8(page.to_vec())?;
+    assert!(!page.contains("://"), "{}", page);
+    assert!(!page.contains("\"/"), "{}", page);
+    Ok(())
+}
@@ -0,0 +1,741 @@
+use std::{num::NonZeroUsize, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::{TimeDelta, Utc};
+use futures::TryStreamExt;
+use shortener_refactor::{
+    AppState, Blocklist, Click, ExportFormat, LinkFilter, LinkOptions, MemoryStorage, RateLimiter,
+    ShortenError, ShortenUrl, SqliteStorage, UrlCache, UNLOCK_LOCKOUT_SECS, UNLOCK_MAX_FAILURES,
+};
+
+async fn states() -> Result<Vec<AppState>> {
+    Ok(vec![
+        AppState::new(Arc::new(MemoryStorage::default())),
+        AppState::new(Arc::new(SqliteStorage::connect("sqlite::memory:").await?)),
+    ])
+}
+
+#[tokio::test]
+async fn shorten_should_dedupe_plain_urls() -> Result<()> {
+    for state in states().await? {
+        let url = "https://www.rust-lang.org/";
+        let shortened = state.shorten(url, LinkOptions::default()).await?;
+        let id = shortened.id;
+        assert_eq!(id.len(), 6);
+        assert!(shortened.token.is_some());
+        let again = state.shorten(url, LinkOptions::default()).await?;
+        assert_eq!(again.id, id);
+        assert!(again.token.is_none());
+        assert_eq!(state.get_url(&id).await?, url);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn shorten_with_alias_should_reject_taken_alias() -> Result<()> {
+    for state in states().await? {
+        let opts = LinkOptions {
+            alias: Some("rust".to_string()),
+            ..Default::default()
+        };
+        let id = state
+            .shorten("https://www.rust-lang.org/", opts.clone())
+            .await?
+            .id;
+        assert_eq!(id, "rust");
+        let ret = state.shorten("https://crates.io/", opts).await;
+        assert!(matches!(ret, Err(ShortenError::AliasTaken(alias)) if alias == "rust"));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn expired_links_should_be_gone_and_purged() -> Result<()> {
+    for state in states().await? {
+        let opts = LinkOptions {
+            expires_at: Some(Utc::now() + TimeDelta::milliseconds(50)),
+            ..Default::default()
+        };
+        let id = state.shorten("https://docs.rs/", opts).await?.id;
+        assert_eq!(state.get_url(&id).await?, "https://docs.rs/");
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(matches!(
+            state.get_url(&id).await,
+            Err(ShortenError::Expired(_))
+        ));
+        assert_eq!(state.purge_expired().await?, 1);
+        assert!(matches!(
+            state.get_url(&id).await,
+            Err(ShortenError::Notfound(_))
+        ));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn stats_should_count_clicks_per_day() -> Result<()> {
+    for state in states().await? {
+        let id = state
+            .shorten("https://lib.rs/", LinkOptions::default())
+            .await?
+            .id;
+        state.record_click(&id, Click::default()).await?;
+        state.record_click(&id, Click::default()).await?;
+        let stats = state.stats(&id).await?;
+        assert_eq!(stats.total_clicks, 2);
+        assert_eq!(stats.daily.len(), 1);
+        assert_eq!(stats.daily[0].day, Utc::now().date_naive());
+        let info = state.info(&id).await?;
+        assert_eq!((info.url.as_str(), info.clicks), ("https://lib.rs/", 2));
+        assert!(info.created_at.is_some());
+        assert!(matches!(
+            state.stats("nope").await,
+            Err(ShortenError::Notfound(_))
+        ));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn shorten_should_normalize_and_validate_urls() -> Result<()> {
+    for state in states().await? {
+        let id = state
+            .shorten("https://www.rust-lang.org/", LinkOptions::default())
+            .await?
+            .id;
+        let same = state
+            .shorten("HTTPS://WWW.Rust-Lang.ORG:443", LinkOptions::default())
+            .await?
+            .id;
+        assert_eq!(id, same);
+        for url in ["ftp://example.com/", "https://", "not a url"] {
+            let ret = state.shorten(url, LinkOptions::default()).await;
+            assert!(matches!(ret, Err(ShortenError::InvalidUrl(_))), "{}", url);
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn shorten_batch_should_report_per_url_results() -> Result<()> {
+    for state in states().await? {
+        let alias = LinkOptions {
+            alias: Some("rustdoc".to_string()),
+            ..Default::default()
+        };
+        let existing = state
+            .shorten("https://crates.io/", LinkOptions::default())
+            .await?
+            .id;
+        let results = state
+            .shorten_batch(vec![
+                ("https://crates.io/".to_string(), LinkOptions::default()),
+                ("https://lib.rs/".to_string(), LinkOptions::default()),
+                ("https://lib.rs/".to_string(), LinkOptions::default()),
+                ("https://docs.rs/".to_string(), alias.clone()),
+                ("https://docs.rs/std".to_string(), alias),
+                ("ftp://example.com/".to_string(), LinkOptions::default()),
+            ])
+            .await?;
+        assert_eq!(results.len(), 6);
+        let id = |i: usize| results[i].as_ref().unwrap().id.as_str();
+        assert_eq!(id(0), existing);
+        assert_eq!(id(1), id(2));
+        assert_eq!(id(3), "rustdoc");
+        assert!(matches!(&results[4], Err(ShortenError::AliasTaken(_))));
+        assert!(matches!(&results[5], Err(ShortenError::InvalidUrl(_))));
+        assert_eq!(state.get_url("rustdoc").await?, "https://docs.rs/");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn owner_token_should_be_required_to_manage_links() -> Result<()> {
+    for state in states().await? {
+        let shortened = state
+            .shorten("https://www.rust-lang.org/", LinkOptions::default())
+            .await?;
+        let (id, token) = (shortened.id, shortened.token.unwrap());
+        let ret = state.retarget(&id, "wrong", "https://crates.io/").await;
+        assert!(matches!(ret, Err(ShortenError::Forbidden(_))));
+
+        state.retarget(&id, &token, "https://crates.io/").await?;
+        assert_eq!(state.get_url(&id).await?, "https://crates.io/");
+        // the old url is no longer shared with the retargeted link
+        let other = state
+            .shorten("https://www.rust-lang.org/", LinkOptions::default())
+            .await?;
+        assert_ne!(other.id, id);
+
+        assert!(matches!(
+            state.delete(&id, "wrong").await,
+            Err(ShortenError::Forbidden(_))
+        ));
+        state.delete(&id, &token).await?;
+        assert!(matches!(
+            state.get_url(&id).await,
+            Err(ShortenError::Notfound(_))
+        ));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn shared_links_should_not_be_managed_once_handed_out_again() -> Result<()> {
+    for state in states().await? {
+        let url = "https://www.rust-lang.org/";
+        let first = state.shorten(url, LinkOptions::default()).await?;
+        let token = first.token.unwrap();
+        let again = state.shorten(url, LinkOptions::default()).await?;
+        assert_eq!(again.id, first.id);
+        for ret in [
+            state
+                .retarget(&first.id, &token, "https://crates.io/")
+                .await,
+            state.delete(&first.id, &token).await,
+        ] {
+            assert!(matches!(ret, Err(ShortenError::SharedLink(_))));
+        }
+        assert_eq!(state.get_url(&first.id).await?, url);
+
+        // the same goes for urls repeated in a batch, except within the batch itself
+        let docs = state
+            .shorten("https://docs.rs/", LinkOptions::default())
+            .await?;
+        let lib = || ("https://lib.rs/".to_string(), LinkOptions::default());
+        let docs_again = ("https://docs.rs/".to_string(), LinkOptions::default());
+        let results = state.shorten_batch(vec![lib(), lib(), docs_again]).await?;
+        let lib = results[0].as_ref().unwrap();
+        state
+            .retarget(
+                &lib.id,
+                lib.token.as_ref().unwrap(),
+                "https://lib.rs/search",
+            )
+            .await?;
+        let ret = state
+            .retarget(&docs.id, &docs.token.unwrap(), "https://docs.rs/std")
+            .await;
+        assert!(matches!(ret, Err(ShortenError::SharedLink(_))));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_keys_should_authenticate_until_revoked() -> Result<()> {
+    for state in states().await? {
+        let key = state.create_api_key("campaigns").await?;
+        let ret = state.create_api_key("campaigns").await;
+        assert!(matches!(ret, Err(ShortenError::ApiKeyExists(_))));
+
+        let api_key = state.authenticate(&key).await?;
+        assert_eq!(api_key.name, "campaigns");
+        let opts = LinkOptions {
+            api_key_id: Some(api_key.id),
+            ..Default::default()
+        };
+        state.shorten("https://www.rust-lang.org/", opts).await?;
+
+        state.revoke_api_key("campaigns").await?;
+        let ret = state.authenticate(&key).await;
+        assert!(matches!(ret, Err(ShortenError::Unauthorized)));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn revoked_api_keys_should_be_kept_for_attribution() -> Result<()> {
+    for state in states().await? {
+        let key = state.create_api_key("campaigns").await?;
+        let api_key = state.authenticate(&key).await?;
+        state.revoke_api_key("campaigns").await?;
+        assert!(matches!(
+            state.authenticate(&key).await,
+            Err(ShortenError::Unauthorized)
+        ));
+        assert!(matches!(
+            state.revoke_api_key("campaigns").await,
+            Err(ShortenError::Notfound(_))
+        ));
+        // the name stays taken
+        assert!(matches!(
+            state.create_api_key("campaigns").await,
+            Err(ShortenError::ApiKeyExists(_))
+        ));
+
+        // links created with it before keep pointing at it
+        let data = format!(
+            r#"{{"id":"kept","url":"https://crates.io/","shared":false,"api_key_id":{}}}"#,
+            api_key.id
+        );
+        let report = state
+            .import(ExportFormat::Jsonl, data.as_bytes(), false)
+            .await?;
+        assert_eq!(report.imported, 1);
+        assert_eq!(state.link("kept").await?.api_key_id, Some(api_key.id));
+    }
+    Ok(())
+}
+
+#[test]
+fn rate_limiter_should_refuse_exhausted_clients() {
+    let limiter = RateLimiter::per_minute(2);
+    assert!(limiter.check("ip:127.0.0.1").is_ok());
+    assert!(limiter.check("ip:127.0.0.1").is_ok());
+    let wait = limiter.check("ip:127.0.0.1").unwrap_err();
+    assert!(wait.as_secs() > 0 && wait.as_secs() <= 30);
+    // other clients have a bucket of their own
+    assert!(limiter.check("ip:10.0.0.1").is_ok());
+}
+
+#[test]
+fn rate_limiter_should_charge_several_tokens_at_once() {
+    let limiter = RateLimiter::per_minute(10);
+    assert!(limiter.check_n("key:1", 4).is_ok());
+    assert!(limiter.check_n("key:1", 7).is_err());
+    assert!(limiter.check_n("key:1", 6).is_ok());
+    assert!(limiter.check("key:1").is_err());
+
+    // a full bucket grants more than it holds, then stays empty for longer
+    assert!(limiter.check_n("key:2", 25).is_ok());
+    let wait = limiter.check("key:2").unwrap_err();
+    assert!(wait.as_secs() >= 90, "{:?}", wait);
+}
+
+#[tokio::test]
+async fn redirect_status_should_be_stored_per_link() -> Result<()> {
+    for state in states().await? {
+        let url = "https://www.rust-lang.org/";
+        let plain = state.shorten(url, LinkOptions::default()).await?.id;
+        let opts = LinkOptions {
+            redirect_status: Some(302),
+            ..Default::default()
+        };
+        let temporary = state.shorten(url, opts).await?.id;
+        assert_ne!(plain, temporary);
+        assert_eq!(state.lookup(&plain).await?.redirect_status, None);
+        assert_eq!(state.lookup(&temporary).await?.redirect_status, Some(302));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn readiness_should_fail_while_draining() -> Result<()> {
+    for state in states().await? {
+        let readiness = state.readiness().await;
+        assert!(readiness.ready);
+        assert_eq!(readiness.database, "ok");
+        state.start_draining();
+        let readiness = state.readiness().await;
+        assert!(!readiness.ready && readiness.draining);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn shorten_should_refuse_blocked_targets() -> Result<()> {
+    for state in states().await? {
+        let blocklist = Blocklist::parse("evil.example\n*.phish.example # lures\n203.0.113.0/24")?;
+        assert_eq!(blocklist.len(), 3);
+        state.set_blocklist(blocklist);
+        for url in [
+            "https://EVIL.example/login",
+            "https://secure.phish.example/",
+            "http://203.0.113.7/",
+        ] {
+            let result = state.shorten(url, LinkOptions::default()).await;
+            assert!(
+                matches!(result, Err(ShortenError::BlockedUrl(_))),
+                "{}",
+                url
+            );
+        }
+        let allowed = state
+            .shorten("https://phish.example/", LinkOptions::default())
+            .await?;
+        let token = allowed.token.unwrap();
+        let retarget = state
+            .retarget(&allowed.id, &token, "https://evil.example/")
+            .await;
+        assert!(matches!(retarget, Err(ShortenError::BlockedUrl(_))));
+
+        state.set_blocklist(Blocklist::private_targets());
+        for url in [
+            "http://localhost:8080/",
+            "http://127.0.0.1/",
+            "http://[::ffff:10.0.0.1]/",
+            "http://192.168.1.1/",
+        ] {
+            let result = state.shorten(url, LinkOptions::default()).await;
+            assert!(
+                matches!(result, Err(ShortenError::BlockedUrl(_))),
+                "{}",
+                url
+            );
+        }
+        let results = state
+            .shorten_batch(vec![
+                ("http://127.0.0.1/".to_string(), LinkOptions::default()),
+                ("https://evil.example/".to_string(), LinkOptions::default()),
+            ])
+            .await?;
+        assert!(matches!(results[0], Err(ShortenError::BlockedUrl(_))));
+        assert!(results[1].is_ok());
+    }
+    assert!(matches!(
+        Blocklist::parse("evil.example\nnot a host"),
+        Err(ShortenError::InvalidBlocklist(_))
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn disabled_links_should_not_resolve() -> Result<()> {
+    for state in states().await? {
+        let url = "https://www.rust-lang.org/";
+        let id = state.shorten(url, LinkOptions::default()).await?.id;
+        assert_eq!(state.get_url(&id).await?, url);
+        state.disable(&id, 451, Some("phishing")).await?;
+        assert!(matches!(
+            state.get_url(&id).await,
+            Err(ShortenError::Disabled(_, 451))
+        ));
+        assert!(matches!(
+            state.info(&id).await,
+            Err(ShortenError::Disabled(_, 451))
+        ));
+        state.disable(&id, 410, None).await?;
+        assert!(matches!(
+            state.get_url(&id).await,
+            Err(ShortenError::Disabled(_, 410))
+        ));
+        state.enable(&id).await?;
+        assert_eq!(state.get_url(&id).await?, url);
+
+        assert!(matches!(
+            state.disable(&id, 404, None).await,
+            Err(ShortenError::InvalidDisableStatus(404))
+        ));
+        assert!(matches!(
+            state.disable("missing", 451, None).await,
+            Err(ShortenError::Notfound(_))
+        ));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn password_links_should_lock_after_wrong_passwords() -> Result<()> {
+    for state in states().await? {
+        let url = "https://www.rust-lang.org/";
+        let plain = state.shorten(url, LinkOptions::default()).await?.id;
+        let opts = LinkOptions {
+            password: Some("hunter2".to_string()),
+            ..Default::default()
+        };
+        let id = state.shorten(url, opts).await?.id;
+        assert_ne!(id, plain);
+        let row = state.lookup(&id).await?;
+        assert!(row
+            .password_hash
+            .is_some_and(|hash| hash.starts_with("$argon2")));
+        assert!(matches!(
+            state.info(&id).await,
+            Err(ShortenError::PasswordRequired(_))
+        ));
+        assert_eq!(state.unlock(&id, "hunter2").await?.url, url);
+        assert_eq!(state.unlock(&plain, "").await?.url, url);
+
+        for _ in 0..*UNLOCK_MAX_FAILURES {
+            assert!(matches!(
+                state.unlock(&id, "wrong").await,
+                Err(ShortenError::WrongPassword(_))
+            ));
+        }
+        // the lockout only just started, so the wait is all of it
+        assert!(matches!(
+            state.unlock(&id, "hunter2").await,
+            Err(ShortenError::Locked(secs)) if secs == *UNLOCK_LOCKOUT_SECS
+        ));
+        // other links are not affected
+        assert!(state.unlock(&plain, "").await.is_ok());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn concurrent_wrong_passwords_should_not_pass_the_lockout() -> Result<()> {
+    for state in states().await? {
+        let opts = LinkOptions {
+            password: Some("hunter2".to_string()),
+            ..Default::default()
+        };
+        let id = state.shorten("https://www.rust-lang.org/", opts).await?.id;
+        let guesses: Vec<_> = (0..40)
+            .map(|_| {
+                let (state, id) = (state.clone(), id.clone());
+                tokio::spawn(async move { state.unlock(&id, "wrong").await })
+            })
+            .collect();
+        let mut verified = 0;
+        for guess in guesses {
+            match guess.await? {
+                Err(ShortenError::WrongPassword(_)) => verified += 1,
+                Err(ShortenError::Locked(_)) => {}
+                other => panic!("unexpected unlock result {:?}", other),
+            }
+        }
+        assert_eq!(verified, *UNLOCK_MAX_FAILURES);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn list_should_filter_and_page_links() -> Result<()> {
+    for state in states().await? {
+        for (alias, url) in [
+            ("aaa", "https://www.rust-lang.org/"),
+            ("bbb", "https://docs.rs/serde"),
+            ("ccc", "https://docs.rs/tokio"),
+        ] {
+            let opts = LinkOptions {
+                alias: Some(alias.to_string()),
+                ..Default::default()
+            };
+            state.shorten(url, opts).await?;
+        }
+        state.disable("ccc", 410, None).await?;
+
+        let ids = |rows: Vec<ShortenUrl>| rows.into_iter().map(|row| row.id).collect::<Vec<_>>();
+        let mut filter = LinkFilter {
+            limit: 2,
+            ..Default::default()
+        };
+        assert_eq!(ids(state.list(&filter).await?), ["aaa", "bbb"]);
+        filter.after = Some("bbb".to_string());
+        assert_eq!(ids(state.list(&filter).await?), ["ccc"]);
+        let filter = LinkFilter {
+            url_contains: Some("docs.rs".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(state.list(&filter).await?), ["bbb", "ccc"]);
+        let filter = LinkFilter {
+            disabled: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(state.list(&filter).await?), ["ccc"]);
+
+        state.admin_retarget("aaa", "https://crates.io/").await?;
+        assert_eq!(state.get_url("aaa").await?, "https://crates.io/");
+        state.admin_delete("bbb").await?;
+        assert!(matches!(
+            state.link("bbb").await,
+            Err(ShortenError::Notfound(_))
+        ));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn import_should_keep_ids_and_report_conflicts() -> Result<()> {
+    for format in [ExportFormat::Jsonl, ExportFormat::Csv] {
+        for state in states().await? {
+            let id = state
+                .shorten("https://www.rust-lang.org/", LinkOptions::default())
+                .await?
+                .id;
+            let opts = LinkOptions {
+                alias: Some("crates".to_string()),
+                password: Some("hunter2".to_string()),
+                ..Default::default()
+            };
+            state.shorten("https://crates.io/", opts).await?;
+            let exported = state.link(&id).await?;
+            assert!(exported.shared);
+            let data: Vec<u8> = state.export(format).try_concat().await?;
+
+            for target in states().await? {
+                let report = target.import(format, &data, true).await?;
+                assert_eq!(report.imported, 2);
+                assert!(report.failed.is_empty());
+                assert!(
+                    target.link(&id).await.is_err(),
+                    "dry run stored {:?}",
+                    format
+                );
+
+                let report = target.import(format, &data, false).await?;
+                assert_eq!(report.imported, 2);
+                let imported = target.link(&id).await?;
+                assert_eq!(imported.url, exported.url);
+                assert_eq!(imported.token_hash, exported.token_hash);
+                assert_eq!(imported.created_at, exported.created_at);
+                assert_eq!(
+                    target.unlock("crates", "hunter2").await?.url,
+                    "https://crates.io/"
+                );
+                // deduplication keeps working against imported links
+                let again = target
+                    .shorten("https://www.rust-lang.org/", LinkOptions::default())
+                    .await?;
+                assert_eq!(again.id, id);
+
+                let report = target.import(format, &data, false).await?;
+                assert_eq!(report.imported, 0);
+                let codes: Vec<_> = report.failed.iter().map(|f| (f.row, f.code)).collect();
+                assert_eq!(codes, [(1, "alias_taken"), (2, "alias_taken")]);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn import_should_check_rows_like_shorten() -> Result<()> {
+    for state in states().await? {
+        let data = [
+            r#"{"id":"good","url":"  HTTPS://Example.COM:443/a","shared":true,"api_key_id":42}"#,
+            "not json",
+            "",
+            r#"{"id":"local","url":"http://127.0.0.1/admin"}"#,
+            r#"{"id":"healthz","url":"https://www.rust-lang.org/"}"#,
+            r#"{"id":"a/b+","url":"https://www.rust-lang.org/"}"#,
+            r#"{"id":"ok200","url":"https://www.rust-lang.org/","redirect_status":200}"#,
+            r#"{"id":"gone","url":"https://www.rust-lang.org/","disabled_status":404}"#,
+            r#"{"id":"again","url":"https://example.com/a","shared":true}"#,
+        ]
+        .join("\n");
+        let report = state
+            .import(ExportFormat::Jsonl, data.as_bytes(), false)
+            .await?;
+        assert_eq!(report.imported, 1);
+        let failed: Vec<_> = report
+            .failed
+            .iter()
+            .map(|f| (f.row, f.id.as_deref(), f.code))
+            .collect();
+        assert_eq!(
+            failed,
+            [
+                (2, None, "invalid_row"),
+                (4, Some("local"), "blocked_url"),
+                (5, Some("healthz"), "invalid_alias"),
+                (6, Some("a/b+"), "invalid_alias"),
+                (7, Some("ok200"), "invalid_redirect_status"),
+                (8, Some("gone"), "invalid_disable_status"),
+                (9, Some("again"), "shared_url_taken"),
+            ]
+        );
+        // stored normalized, without the key this database does not know
+        let row = state.link("good").await?;
+        assert_eq!(row.url, "https://example.com/a");
+        assert_eq!(row.api_key_id, None);
+        let again = state
+            .shorten(" https://EXAMPLE.com/a", LinkOptions::default())
+            .await?;
+        assert_eq!(again.id, "good");
+    }
+    Ok(())
+}
+
+fn cached_row(id: &str) -> ShortenUrl {
+    ShortenUrl {
+        id: id.to_string(),
+        url: format!("https://crates.io/crates/{}", id),
+        shared: false,
+        expires_at: None,
+        token_hash: None,
+        created_at: None,
+        api_key_id: None,
+        redirect_status: None,
+        disabled_status: None,
+        disabled_reason: None,
+        password_hash: None,
+    }
+}
+
+#[test]
+fn url_cache_should_evict_the_least_recently_used() {
+    let cache = UrlCache::new(
+        NonZeroUsize::new(2).unwrap(),
+        Duration::from_secs(60),
+        Duration::from_secs(60),
+    );
+    cache.insert("a", Some(cached_row("a")));
+    cache.insert("b", Some(cached_row("b")));
+    assert!(cache.get("a").is_some());
+    cache.insert("c", None);
+    assert!(cache.get("b").is_none(), "b was used least recently");
+    assert_eq!(
+        cache.get("a").flatten().map(|row| row.id),
+        Some("a".to_string())
+    );
+    assert!(matches!(cache.get("c"), Some(None)));
+    assert_eq!((cache.hits(), cache.misses()), (3, 1));
+}
+
+#[tokio::test]
+async fn url_cache_should_expire_rows_and_misses_after_their_ttl() {
+    let cache = UrlCache::new(
+        NonZeroUsize::new(8).unwrap(),
+        Duration::from_millis(200),
+        Duration::from_millis(50),
+    );
+    cache.insert("found", Some(cached_row("found")));
+    cache.insert("missing", None);
+    assert!(cache.get("found").is_some());
+    assert!(matches!(cache.get("missing"), Some(None)));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(cache.get("found").is_some());
+    assert!(cache.get("missing").is_none());
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(cache.get("found").is_none());
+}
+
+#[tokio::test]
+async fn shorten_should_replace_a_cached_miss() -> Result<()> {
+    for state in states().await? {
+        assert!(matches!(
+            state.get_url("later").await,
+            Err(ShortenError::Notfound(_))
+        ));
+        let opts = LinkOptions {
+            alias: Some("later".to_string()),
+            ..Default::default()
+        };
+        state.shorten("https://docs.rs/", opts).await?;
+        assert_eq!(state.get_url("later").await?, "https://docs.rs/");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn changes_should_not_be_hidden_by_cached_rows() -> Result<()> {
+    for state in states().await? {
+        let shortened = state
+            .shorten("https://www.rust-lang.org/", LinkOptions::default())
+            .await?;
+        let (id, token) = (shortened.id, shortened.token.unwrap());
+        assert_eq!(state.get_url(&id).await?, "https://www.rust-lang.org/");
+
+        state.retarget(&id, &token, "https://crates.io/").await?;
+        assert_eq!(state.get_url(&id).await?, "https://crates.io/");
+
+        state.disable(&id, 410, None).await?;
+        assert!(matches!(
+            state.get_url(&id).await,
+            Err(ShortenError::Disabled(_, 410))
+        ));
+        state.enable(&id).await?;
+        assert_eq!(state.get_url(&id).await?, "https://crates.io/");
+
+        state.delete(&id, &token).await?;
+        assert!(matches!(
+            state.get_url(&id).await,
+            Err(ShortenError::Notfound(_))
+        ));
+    }
+    Ok(())
+}